@@ -0,0 +1,529 @@
+use serde_json::Value;
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::sync::Arc;
+use structopt::StructOpt;
+use validator::Validate;
+
+use crate::stupiddb::auth;
+use crate::stupiddb::cache::RepositoryCache;
+use crate::stupiddb::config::{self, ConfigOverrides, SharedConfig};
+use crate::stupiddb::encryption;
+use crate::stupiddb::error::StupidDbError;
+use crate::stupiddb::index;
+use crate::stupiddb::models::{
+    StupidBlob, StupidChangeEvent, StupidChangeKind, StupidDbRepositoryConfig, StupidLogLevel,
+    StupidPushRequest, StupidRole, StupidSignatureStatus, StupidToken,
+};
+use crate::stupiddb::repository::DEFAULT_REMOTE;
+use crate::stupiddb::StupidDb;
+
+/// Git backed JSON document store.
+///
+/// Commands other than serve work on the repositories under the root dir
+/// directly, they do not need a running server.
+///
+/// Settings are read from the configuration file, then from STUPIDDB_*
+/// environment variables, then from the flags, the last one winning.
+#[derive(StructOpt, Debug)]
+#[structopt(name = "stupiddb")]
+pub struct StupidCli {
+    /// Configuration file, the one of the user when not given
+    #[structopt(long, global = true, parse(from_os_str))]
+    pub config: Option<PathBuf>,
+    /// Directory holding the repositories
+    #[structopt(long, global = true)]
+    pub root_dir: Option<String>,
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl StupidCli {
+    /// Settings the flags override
+    pub fn overrides(&self) -> ConfigOverrides {
+        let mut overrides = ConfigOverrides {
+            file: self.config.clone(),
+            root_dir: self.root_dir.clone(),
+            ..ConfigOverrides::default()
+        };
+
+        if let Some(Command::Serve {
+            address,
+            port,
+            workers,
+            log_level,
+        }) = self.command.as_ref()
+        {
+            overrides.address = address.clone();
+            overrides.port = *port;
+            overrides.workers = *workers;
+            overrides.log_level = *log_level;
+        }
+
+        overrides
+    }
+}
+
+#[derive(StructOpt, Debug)]
+pub enum Command {
+    /// Starts the HTTP server, the default without a command
+    Serve {
+        #[structopt(long)]
+        address: Option<String>,
+        #[structopt(long)]
+        port: Option<u16>,
+        /// Threads serving requests
+        #[structopt(long)]
+        workers: Option<u16>,
+        /// One of critical, normal, debug or off
+        #[structopt(long, parse(try_from_str = config::parse_log_level))]
+        log_level: Option<StupidLogLevel>,
+    },
+    /// Creates a repository
+    Init {
+        repository: String,
+        #[structopt(long)]
+        origin: Option<String>,
+    },
+    /// Writes a document read from a file or from stdin
+    Put {
+        repository: String,
+        name: String,
+        #[structopt(long, short, parse(from_os_str))]
+        file: Option<PathBuf>,
+        #[structopt(long, short)]
+        message: Option<String>,
+        #[structopt(long, short)]
+        branch: Option<String>,
+    },
+    /// Prints a document
+    Get {
+        repository: String,
+        name: String,
+        /// Commit, tag or branch to read at, HEAD when not given
+        #[structopt(long, short)]
+        rev: Option<String>,
+    },
+    /// Removes a document
+    Rm {
+        repository: String,
+        name: String,
+        #[structopt(long, short)]
+        message: Option<String>,
+    },
+    /// Lists repositories, or the documents of a repository
+    Ls {
+        repository: Option<String>,
+        #[structopt(long, short)]
+        rev: Option<String>,
+    },
+    /// Shows the commits of a repository
+    Log {
+        repository: String,
+        #[structopt(long, short)]
+        rev: Option<String>,
+        /// Number of commits shown
+        #[structopt(long, short = "n")]
+        limit: Option<usize>,
+    },
+    /// Shows the changed values between two revisions
+    Diff {
+        repository: String,
+        from: String,
+        /// HEAD when not given
+        to: Option<String>,
+    },
+    /// Pushes to origin, or to the given remote
+    Push {
+        repository: String,
+        #[structopt(long, short)]
+        remote: Option<String>,
+        /// Pushes to every remote
+        #[structopt(long)]
+        all: bool,
+        /// Pushes all tags along
+        #[structopt(long)]
+        tags: bool,
+    },
+    /// Fetches from origin, or from the given remote
+    Fetch {
+        repository: String,
+        #[structopt(long, short)]
+        remote: Option<String>,
+    },
+    /// Encrypts the documents of a repository again with the current key
+    Rotate {
+        repository: String,
+        #[structopt(long, short)]
+        message: Option<String>,
+    },
+    /// Manages the secondary indexes of a repository
+    Index(IndexCommand),
+    /// Manages the tokens of the API
+    Token(TokenCommand),
+}
+
+#[derive(StructOpt, Debug)]
+pub enum IndexCommand {
+    /// Builds the index of a branch again from its commit
+    Rebuild {
+        repository: String,
+        /// The branch HEAD points at when not given
+        #[structopt(long, short)]
+        branch: Option<String>,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+pub enum TokenCommand {
+    /// Creates a token and prints it, it cannot be shown again
+    Create {
+        name: String,
+        /// Role on a repository as <repository>=<role>, * standing for
+        /// every repository. Roles are read, write, push and admin.
+        #[structopt(long, short, parse(try_from_str = parse_role))]
+        role: Vec<(String, StupidRole)>,
+    },
+    /// Lists tokens
+    Ls,
+    /// Revokes a token
+    Rm { id: String },
+}
+
+/// Runs a command other than serve
+pub fn run(command: Command, config: &SharedConfig) -> Result<(), StupidDbError> {
+    // Nothing outlives the command, so there is nothing worth caching
+    let cache = Arc::new(RepositoryCache::new(0));
+    let mut db = StupidDb::new(config, &cache);
+    match command {
+        Command::Serve { .. } => Ok(()),
+        Command::Init { repository, origin } => init(&mut db, repository, origin),
+        Command::Put {
+            repository,
+            name,
+            file,
+            message,
+            branch,
+        } => {
+            let content = match file {
+                Some(path) => fs::read_to_string(path).ok(),
+                None => {
+                    let mut content = String::new();
+                    io::stdin()
+                        .read_to_string(&mut content)
+                        .ok()
+                        .map(|_| content)
+                }
+            };
+            let content: Option<Value> = content.and_then(|c| serde_json::from_str(&c).ok());
+            if content.is_none() {
+                return Err(StupidDbError::ContentInvalid);
+            }
+
+            let blob = StupidBlob {
+                id: None,
+                oid: None,
+                message,
+                name,
+                content: content.unwrap(),
+            };
+            if blob.validate().is_err() {
+                return Err(StupidDbError::ContentInvalid);
+            }
+
+            let open_result = db.open(&repository);
+            if open_result.is_err() {
+                return Err(open_result.err().unwrap());
+            }
+
+            db.write(&blob, branch.as_ref().map(|b| b.as_str()))
+                .map(|b| println!("{}", b.id.unwrap_or_default()))
+        }
+        Command::Get {
+            repository,
+            name,
+            rev,
+        } => db
+            .open(&repository)
+            .and_then(|repo| repo.read(name.as_str(), rev.as_ref().map(|r| r.as_str())))
+            .map(|blob| println!("{}", pretty(&blob.content))),
+        Command::Rm {
+            repository,
+            name,
+            message,
+        } => {
+            let open_result = db.open(&repository);
+            if open_result.is_err() {
+                return Err(open_result.err().unwrap());
+            }
+
+            db.remove(name.as_str(), message.as_ref().map(|m| m.as_str()))
+        }
+        Command::Ls {
+            repository: None, ..
+        } => {
+            db.names().iter().for_each(|name| println!("{}", name));
+            Ok(())
+        }
+        Command::Ls {
+            repository: Some(repository),
+            rev,
+        } => db
+            .open(&repository)
+            .and_then(|repo| repo.documents(rev.as_ref().map(|r| r.as_str())))
+            .map(|names| names.iter().for_each(|name| println!("{}", name))),
+        Command::Log {
+            repository,
+            rev,
+            limit,
+        } => db
+            .open(&repository)
+            .and_then(|repo| repo.log(rev.as_ref().map(|r| r.as_str()), limit, &|_| true))
+            .map(|events| events.iter().for_each(print_commit)),
+        Command::Diff {
+            repository,
+            from,
+            to,
+        } => diff(&mut db, repository, from, to),
+        Command::Push {
+            repository,
+            remote,
+            all,
+            tags,
+        } => {
+            let request = StupidPushRequest {
+                remote,
+                all_remotes: all,
+                tags,
+                ..StupidPushRequest::default()
+            };
+            db.open(&repository)
+                .and_then(|repo| repo.push(&request))
+                .map(|results| {
+                    for result in results {
+                        match result.error {
+                            Some(e) => println!("{}: {}", result.remote, e),
+                            None => result.references.iter().for_each(|r| {
+                                let status = r.status.as_ref().map_or("ok", |s| s.as_str());
+                                println!("{}: {} {}", result.remote, r.reference, status)
+                            }),
+                        }
+                    }
+                })
+        }
+        Command::Fetch { repository, remote } => db
+            .open(&repository)
+            .and_then(|repo| repo.fetch(remote.as_ref().map_or(DEFAULT_REMOTE, |r| r.as_str()))),
+        Command::Rotate {
+            repository,
+            message,
+        } => db
+            .open(&repository)
+            .and_then(|repo| encryption::rotate(repo, message.as_ref().map(|m| m.as_str())))
+            .map(|result| {
+                result
+                    .documents
+                    .iter()
+                    .for_each(|name| println!("{}", name));
+                if let Some(commit) = result.commit {
+                    println!("commit {}", commit);
+                }
+            }),
+        Command::Index(IndexCommand::Rebuild { repository, branch }) => {
+            let reference = branch.map(|b| format!("refs/heads/{}", b));
+            db.open(&repository)
+                .and_then(|repo| index::rebuild(repo, reference.as_ref().map(|r| r.as_str())))
+                .map(|status| {
+                    status.paths.iter().for_each(|path| println!("{}", path));
+                    if let Some(commit) = status.commit {
+                        println!("commit {}", commit);
+                    }
+                })
+        }
+        Command::Token(command) => token(db.root_dir.as_str(), command),
+    }
+}
+
+fn token(root_dir: &str, command: TokenCommand) -> Result<(), StupidDbError> {
+    match command {
+        TokenCommand::Create { name, role } => {
+            let token = StupidToken {
+                id: String::new(),
+                name,
+                roles: role.into_iter().collect(),
+                created: 0,
+                secret: None,
+            };
+            if token.validate().is_err() {
+                return Err(StupidDbError::ContentInvalid);
+            }
+
+            auth::create(root_dir, &token)
+                .map(|t| println!("{} {}", t.id, t.secret.unwrap_or_default()))
+        }
+        TokenCommand::Ls => auth::list(root_dir).map(|tokens| {
+            for token in tokens {
+                let mut roles: Vec<String> = token
+                    .roles
+                    .iter()
+                    .map(|(repository, role)| format!("{}={}", repository, role_name(*role)))
+                    .collect();
+                roles.sort();
+                println!("{} {} {}", token.id, token.name, roles.join(","));
+            }
+        }),
+        TokenCommand::Rm { id } => auth::revoke(root_dir, id.as_str()),
+    }
+}
+
+fn parse_role(role: &str) -> Result<(String, StupidRole), String> {
+    let mut parts = role.splitn(2, '=');
+    let repository = parts.next().unwrap_or_default();
+    let name = parts.next().unwrap_or_default();
+    serde_json::from_value(Value::String(name.to_lowercase()))
+        .map(|r| (String::from(repository), r))
+        .map_err(|_| {
+            format!(
+                "expected <repository>=<role> with one of read, write, push or admin, got \"{}\"",
+                role
+            )
+        })
+}
+
+fn role_name(role: StupidRole) -> String {
+    serde_json::to_value(role)
+        .ok()
+        .and_then(|v| v.as_str().map(String::from))
+        .unwrap_or_default()
+}
+
+fn init(db: &mut StupidDb, name: String, origin: Option<String>) -> Result<(), StupidDbError> {
+    let config = StupidDbRepositoryConfig {
+        name,
+        origin,
+        credentials: None,
+        push_refspecs: None,
+        remotes: Vec::new(),
+        mirror: None,
+        mirror_status: None,
+    };
+    if config.validate().is_err() {
+        return Err(StupidDbError::RepoPathInvalid);
+    }
+
+    let result = db.init(&config);
+    if result.is_err() || config.origin.is_none() {
+        return result;
+    }
+
+    db.open(&config.name)
+        .and_then(|repo| repo.set_remoteurl(DEFAULT_REMOTE, config.origin.as_ref().unwrap()))
+}
+
+fn diff(
+    db: &mut StupidDb,
+    repository: String,
+    from: String,
+    to: Option<String>,
+) -> Result<(), StupidDbError> {
+    let open_result = db.open(&repository);
+    if open_result.is_err() {
+        return Err(open_result.err().unwrap());
+    }
+
+    let repo = open_result.unwrap();
+    let from_tree = repo.find_revision_tree(from.as_str());
+    let to_tree = match to.as_ref() {
+        Some(t) => repo.find_revision_tree(t.as_str()),
+        None => repo.find_revision_tree("HEAD"),
+    };
+    if from_tree.is_none() || to_tree.is_none() {
+        return Err(StupidDbError::RevisionNotFound);
+    }
+
+    let deltas = repo.diff_documents(from_tree.as_ref(), to_tree.as_ref());
+    if deltas.is_err() {
+        return Err(deltas.err().unwrap());
+    }
+
+    for delta in deltas.unwrap() {
+        let old = delta.old.and_then(|oid| repo.read_blob_value(oid).ok());
+        let new = delta.new.and_then(|oid| repo.read_blob_value(oid).ok());
+        match (old, new) {
+            (None, Some(_)) => println!("A {}", delta.name),
+            (Some(_), None) => println!("D {}", delta.name),
+            (old, new) => {
+                println!("M {}", delta.name);
+                let mut changes = Vec::new();
+                changed_values(old.as_ref(), new.as_ref(), String::new(), &mut changes);
+                for (path, old, new) in changes {
+                    println!("    {}: {} -> {}", path, text(old), text(new));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Paths whose values differ, objects compared key by key
+fn changed_values<'a>(
+    old: Option<&'a Value>,
+    new: Option<&'a Value>,
+    path: String,
+    changes: &mut Vec<(String, Option<&'a Value>, Option<&'a Value>)>,
+) {
+    match (old, new) {
+        (Some(Value::Object(o)), Some(Value::Object(n))) => {
+            let mut keys: Vec<&String> = o.keys().chain(n.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let token = key.replace('~', "~0").replace('/', "~1");
+                changed_values(
+                    o.get(key),
+                    n.get(key),
+                    format!("{}/{}", path, token),
+                    changes,
+                );
+            }
+        }
+        (o, n) if o != n => changes.push((path, o, n)),
+        _ => {}
+    }
+}
+
+fn print_commit(event: &StupidChangeEvent) {
+    println!("commit {}", event.commit);
+    if let Some(signature) = event.signature.as_ref() {
+        let status = match signature {
+            StupidSignatureStatus::Unsigned => "unsigned",
+            StupidSignatureStatus::Good => "good",
+            StupidSignatureStatus::Bad => "BAD",
+            StupidSignatureStatus::Unknown => "unknown key",
+        };
+        println!("Signature: {}", status);
+    }
+    println!("Date:   {}", event.time);
+    println!();
+    println!("    {}", event.message.as_ref().map_or("", |m| m.trim()));
+    println!();
+    for change in event.changes.iter() {
+        let kind = match change.kind {
+            StupidChangeKind::Added => "A",
+            StupidChangeKind::Modified => "M",
+            StupidChangeKind::Deleted => "D",
+        };
+        println!("    {} {}", kind, change.name);
+    }
+    println!();
+}
+
+fn pretty(value: &Value) -> String {
+    serde_json::to_string_pretty(value).unwrap_or_default()
+}
+
+fn text(value: Option<&Value>) -> String {
+    value.map_or(String::from("(none)"), |v| v.to_string())
+}
@@ -9,19 +9,124 @@ extern crate validator;
 #[macro_use]
 extern crate validator_derive;
 
+use rocket::config::{Config, Environment, Limits, LoggingLevel};
+use std::sync::{Arc, RwLock};
+use structopt::StructOpt;
+
+use stupiddb::cache::{RepositoryCache, SharedCache};
+use stupiddb::config::SharedConfig;
+use stupiddb::models::{StupidConfig, StupidLogLevel};
+
+mod cli;
 mod rest;
 mod stupiddb;
 
 fn main() {
-    rocket::ignite()
+    let options = cli::StupidCli::from_args();
+    let config = stupiddb::config::load(&options.overrides());
+    if config.is_err() {
+        eprintln!("error: {}", config.err().unwrap());
+        std::process::exit(1);
+    }
+
+    let config: SharedConfig = Arc::new(RwLock::new(config.unwrap()));
+    match options.command {
+        None | Some(cli::Command::Serve { .. }) => serve(config),
+        Some(command) => {
+            let result = cli::run(command, &config);
+            if result.is_err() {
+                eprintln!("error: {:?}", result.err().unwrap());
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn serve(config: SharedConfig) {
+    let rocket_config = rocket_config(&config.read().unwrap());
+    if rocket_config.is_err() {
+        eprintln!("error: {}", rocket_config.err().unwrap());
+        std::process::exit(1);
+    }
+
+    let capacity = config.read().unwrap().limits.open_repositories;
+    let cache: SharedCache = Arc::new(RepositoryCache::new(capacity));
+    stupiddb::mirror::start(&config, &cache);
+    stupiddb::webhook::start(&config, &cache);
+    stupiddb::autopush::start(&config, &cache);
+
+    rocket::custom(rocket_config.unwrap())
+        .manage(config)
+        .manage(cache)
         .mount(
             "/",
             routes![
                 rest::repository::post,
+                rest::repository::list,
                 rest::repository::get,
+                rest::repository::delete,
+                rest::repository::rename,
+                rest::repository::info,
                 rest::repository::push,
+                rest::remote::list,
+                rest::remote::post,
+                rest::remote::put,
+                rest::remote::delete,
+                rest::branch::list,
+                rest::branch::post,
+                rest::branch::delete,
+                rest::branch::merge,
+                rest::tag::list,
+                rest::tag::post,
+                rest::revert::document,
+                rest::revert::commit,
+                rest::changes::changes,
+                rest::webhook::list,
+                rest::webhook::post,
+                rest::webhook::delete,
+                rest::webhook::deliveries,
+                rest::index::get,
+                rest::index::put,
+                rest::index::rebuild,
+                rest::encryption::get,
+                rest::encryption::put,
+                rest::encryption::rotate,
+                rest::query::query,
+                rest::search::search,
+                rest::search::rebuild,
+                rest::history::pickaxe,
+                rest::history::log,
+                rest::token::list,
+                rest::token::post,
+                rest::token::delete,
                 rest::data::post,
+                rest::data::get,
+                rest::data::delete,
+                rest::data::list,
             ],
         )
         .launch();
 }
+
+/// Rocket settings from the server section, Rocket.toml and ROCKET_*
+/// variables are not read
+fn rocket_config(config: &StupidConfig) -> Result<Config, rocket::config::ConfigError> {
+    let environment = Environment::active().unwrap_or(Environment::Development);
+    let log_level = match config.server.log_level {
+        StupidLogLevel::Critical => LoggingLevel::Critical,
+        StupidLogLevel::Normal => LoggingLevel::Normal,
+        StupidLogLevel::Debug => LoggingLevel::Debug,
+        StupidLogLevel::Off => LoggingLevel::Off,
+    };
+
+    let mut builder = Config::build(environment)
+        .address(config.server.address.as_str())
+        .port(config.server.port)
+        .log_level(log_level)
+        .limits(Limits::new().limit("json", config.limits.document_size));
+    if let Some(workers) = config.server.workers {
+        builder = builder.workers(workers);
+    }
+
+    builder.finalize()
+}
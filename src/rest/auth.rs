@@ -0,0 +1,292 @@
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::{Outcome, State};
+use std::ops::Deref;
+
+use crate::stupiddb::auth;
+use crate::stupiddb::config::SharedConfig;
+use crate::stupiddb::error::StupidDbError;
+use crate::stupiddb::models::{StupidRole, StupidToken, ALL_REPOSITORIES};
+
+const BEARER: &str = "Bearer ";
+
+/// A token with a role on the repository of the route, the repository
+/// being its first dynamic segment
+pub struct ReadAccess;
+pub struct WriteAccess;
+pub struct PushAccess;
+pub struct AdminAccess;
+
+/// A token with the admin role on every repository
+pub struct ServerAdmin;
+
+/// Any known token, for routes not about a single repository. The token
+/// is None when authentication is disabled.
+pub struct Authenticated(pub Option<StupidToken>);
+
+impl Authenticated {
+    /// Whether the token may read a repository, or some of its documents
+    pub fn can_read(&self, repository: &str) -> bool {
+        self.0
+            .as_ref()
+            .map_or(true, |t| t.allows_some(repository, StupidRole::Read))
+    }
+}
+
+/// A token with a role on the repository of the route or on some of its
+/// documents, the documents it may not see are left out of responses
+#[derive(Debug)]
+pub struct DocumentAccess {
+    repository: String,
+    role: StupidRole,
+    token: Option<StupidToken>,
+}
+
+impl DocumentAccess {
+    /// Whether the token has the role on a document
+    pub fn allows(&self, name: &str) -> bool {
+        self.token.as_ref().map_or(true, |t| {
+            t.allows_document(self.repository.as_str(), name, self.role)
+        })
+    }
+
+    /// Fails with Forbidden unless the token has the role on a document
+    pub fn check(&self, name: &str) -> Result<(), StupidDbError> {
+        match self.allows(name) {
+            true => Ok(()),
+            false => Err(StupidDbError::Forbidden),
+        }
+    }
+}
+
+pub struct ReadDocuments(pub DocumentAccess);
+pub struct WriteDocuments(pub DocumentAccess);
+
+impl Deref for ReadDocuments {
+    type Target = DocumentAccess;
+
+    fn deref(&self) -> &DocumentAccess {
+        &self.0
+    }
+}
+
+impl Deref for WriteDocuments {
+    type Target = DocumentAccess;
+
+    fn deref(&self) -> &DocumentAccess {
+        &self.0
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for ReadDocuments {
+    type Error = StupidDbError;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, StupidDbError> {
+        authorize_documents(request, StupidRole::Read).map(ReadDocuments)
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for WriteDocuments {
+    type Error = StupidDbError;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, StupidDbError> {
+        authorize_documents(request, StupidRole::Write).map(WriteDocuments)
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for ReadAccess {
+    type Error = StupidDbError;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, StupidDbError> {
+        authorize(request, repository(request), Some(StupidRole::Read)).map(|_| ReadAccess)
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for WriteAccess {
+    type Error = StupidDbError;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, StupidDbError> {
+        authorize(request, repository(request), Some(StupidRole::Write)).map(|_| WriteAccess)
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for PushAccess {
+    type Error = StupidDbError;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, StupidDbError> {
+        authorize(request, repository(request), Some(StupidRole::Push)).map(|_| PushAccess)
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for AdminAccess {
+    type Error = StupidDbError;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, StupidDbError> {
+        authorize(request, repository(request), Some(StupidRole::Admin)).map(|_| AdminAccess)
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for ServerAdmin {
+    type Error = StupidDbError;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, StupidDbError> {
+        let all = Some(String::from(ALL_REPOSITORIES));
+        authorize(request, all, Some(StupidRole::Admin)).map(|_| ServerAdmin)
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Authenticated {
+    type Error = StupidDbError;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, StupidDbError> {
+        authorize(request, None, None).map(Authenticated)
+    }
+}
+
+/// Checks the bearer token of a request for a role on a repository,
+/// without a role any known token will do
+fn authorize(
+    request: &Request,
+    repository: Option<String>,
+    role: Option<StupidRole>,
+) -> request::Outcome<Option<StupidToken>, StupidDbError> {
+    let config = request.guard::<State<SharedConfig>>();
+    if !config.is_success() {
+        return Outcome::Failure((Status::InternalServerError, StupidDbError::ConfigFailed));
+    }
+
+    let (enabled, root_dir) = {
+        let config = config.unwrap();
+        let config = config.read().unwrap();
+        (config.auth.enabled, config.root_dir.clone())
+    };
+    if !enabled {
+        return Outcome::Success(None);
+    }
+
+    let token = request
+        .headers()
+        .get_one("Authorization")
+        .filter(|h| h.starts_with(BEARER))
+        .and_then(|h| auth::authenticate(root_dir.as_str(), h[BEARER.len()..].trim()));
+    if token.is_none() {
+        return Outcome::Failure((Status::Unauthorized, StupidDbError::Unauthorized));
+    }
+
+    let token = token.unwrap();
+    let allowed = match (repository, role) {
+        (_, None) => true,
+        (Some(repository), Some(role)) => token.allows(repository.as_str(), role),
+        (None, Some(_)) => false,
+    };
+    if !allowed {
+        return Outcome::Failure((Status::Forbidden, StupidDbError::Forbidden));
+    }
+
+    Outcome::Success(Some(token))
+}
+
+/// Checks the bearer token of a request for a role on the repository of
+/// the route or on any of its documents
+fn authorize_documents(
+    request: &Request,
+    role: StupidRole,
+) -> request::Outcome<DocumentAccess, StupidDbError> {
+    let repository = repository(request).unwrap_or_default();
+    let token = authorize(request, None, None);
+    let token = match token {
+        Outcome::Success(t) => t,
+        Outcome::Failure(f) => return Outcome::Failure(f),
+        Outcome::Forward(f) => return Outcome::Forward(f),
+    };
+
+    if !token
+        .as_ref()
+        .map_or(true, |t| t.allows_some(repository.as_str(), role))
+    {
+        return Outcome::Failure((Status::Forbidden, StupidDbError::Forbidden));
+    }
+
+    Outcome::Success(DocumentAccess {
+        repository: repository,
+        role: role,
+        token: token,
+    })
+}
+
+/// Repository of the route, its first dynamic segment
+fn repository(request: &Request) -> Option<String> {
+    request.get_param::<String>(0).and_then(|p| p.ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn access(roles: &[(&str, StupidRole)], role: StupidRole) -> DocumentAccess {
+        DocumentAccess {
+            repository: String::from("config.git"),
+            role,
+            token: Some(StupidToken {
+                id: String::new(),
+                name: String::from("test"),
+                roles: roles
+                    .iter()
+                    .map(|(key, role)| (String::from(*key), *role))
+                    .collect(),
+                created: 0,
+                secret: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn allows_everything_without_authentication() {
+        let access = DocumentAccess {
+            repository: String::from("config.git"),
+            role: StupidRole::Write,
+            token: None,
+        };
+        assert!(access.allows("any"));
+        assert!(access.check("any").is_ok());
+    }
+
+    #[test]
+    fn allows_documents_matching_a_prefix() {
+        let access = access(
+            &[("config.git/payments.*", StupidRole::Read)],
+            StupidRole::Read,
+        );
+        assert!(access.allows("payments.eu"));
+        assert!(!access.allows("invoices"));
+        assert!(access.check("payments.us").is_ok());
+        assert!(matches!(
+            access.check("invoices"),
+            Err(StupidDbError::Forbidden)
+        ));
+    }
+
+    #[test]
+    fn lists_repositories_with_a_document_role() {
+        let access = access(
+            &[("config.git/payments.*", StupidRole::Read)],
+            StupidRole::Read,
+        );
+        let authenticated = Authenticated(access.token);
+        assert!(authenticated.can_read("config.git"));
+        assert!(!authenticated.can_read("other.git"));
+    }
+
+    #[test]
+    fn checks_the_role_of_the_route() {
+        let access = access(
+            &[("config.git/payments.*", StupidRole::Read)],
+            StupidRole::Write,
+        );
+        assert!(!access.allows("payments.eu"));
+        assert!(matches!(
+            access.check("payments.eu"),
+            Err(StupidDbError::Forbidden)
+        ));
+    }
+}
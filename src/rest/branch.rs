@@ -0,0 +1,93 @@
+use rocket::http::Status;
+use rocket::response::status;
+use rocket_contrib::json::Json;
+
+use super::auth::{ReadAccess, WriteAccess};
+use crate::stupiddb::error::StupidDbError;
+use crate::stupiddb::merge::MergeOptions;
+use crate::stupiddb::models::{
+    StupidBranch, StupidBranchRequest, StupidMergeRequest, StupidMergeResult, StupidMergeStatus,
+};
+use crate::stupiddb::StupidDb;
+
+#[get("/<name>/branches", format = "application/json")]
+pub fn list(
+    name: String,
+    _access: ReadAccess,
+    mut db: StupidDb,
+) -> Result<Json<Vec<StupidBranch>>, StupidDbError> {
+    let repository = db.open(&name);
+    if repository.is_err() {
+        return Err(repository.err().unwrap());
+    }
+
+    repository.unwrap().branches().map(|b| Json(b))
+}
+
+#[post("/<name>/branches", format = "application/json", data = "<request>")]
+pub fn post(
+    name: String,
+    request: StupidBranchRequest,
+    _access: WriteAccess,
+    mut db: StupidDb,
+) -> Result<Json<StupidBranch>, StupidDbError> {
+    let repository = db.open(&name);
+    if repository.is_err() {
+        return Err(repository.err().unwrap());
+    }
+
+    repository
+        .unwrap()
+        .create_branch(
+            request.name.as_str(),
+            request.from.as_ref().map(|f| f.as_str()),
+        )
+        .map(|b| Json(b))
+}
+
+#[delete("/<name>/branches/<branch>")]
+pub fn delete(
+    name: String,
+    branch: String,
+    _access: WriteAccess,
+    mut db: StupidDb,
+) -> Result<(), StupidDbError> {
+    let repository = db.open(&name);
+    if repository.is_err() {
+        return Err(repository.err().unwrap());
+    }
+
+    repository.unwrap().delete_branch(branch.as_str())
+}
+
+/// Merges may touch any document, so they take a role on the whole
+/// repository
+#[post("/<name>/merges", format = "application/json", data = "<request>")]
+pub fn merge(
+    name: String,
+    request: StupidMergeRequest,
+    _access: WriteAccess,
+    mut db: StupidDb,
+) -> Result<status::Custom<Json<StupidMergeResult>>, StupidDbError> {
+    let repository = db.open(&name);
+    if repository.is_err() {
+        return Err(repository.err().unwrap());
+    }
+
+    let repo = repository.unwrap();
+    let options = MergeOptions {
+        arrays: request.arrays.clone(),
+        paths: request.array_paths.clone(),
+    };
+
+    repo.merge_branches(
+        request.source.as_str(),
+        request.target.as_str(),
+        request.message.as_ref().map(|m| m.as_str()),
+        &options,
+    )
+    .map(|result| match result.status {
+        StupidMergeStatus::Conflict => status::Custom(Status::Conflict, Json(result)),
+        _ => status::Custom(Status::Ok, Json(result)),
+    })
+}
@@ -0,0 +1,177 @@
+use rocket::http::ContentType;
+use rocket::response::{Content, Stream};
+use rocket::State;
+use std::collections::{HashSet, VecDeque};
+use std::io::{self, Read};
+use std::sync::atomic::AtomicUsize;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use super::auth::{DocumentAccess, ReadDocuments};
+use crate::stupiddb::config::SharedConfig;
+use crate::stupiddb::error::StupidDbError;
+use crate::stupiddb::events;
+use crate::stupiddb::models::StupidChangeEvent;
+use crate::stupiddb::StupidDb;
+
+const KEEP_ALIVE_SECONDS: u64 = 15;
+const CHUNK_SIZE: u64 = 4096;
+
+/// Change feeds streaming right now
+static FEEDS: AtomicUsize = AtomicUsize::new(0);
+
+/// Streams the commits of a branch as server-sent events.
+///
+/// Commits after `since` are replayed from history first, so a client
+/// that reconnects with the last id it saw does not miss anything. Only the
+/// changes to documents the token may read are sent.
+///
+/// A feed holds a worker for as long as its client stays connected, so at
+/// most `limits.change_feeds` are open at the same time and further ones
+/// get a 503. A client that went away is noticed once keep-alives fail to
+/// reach it.
+#[get("/<repository>/_changes?<since>&<branch>")]
+pub fn changes(
+    repository: String,
+    since: Option<String>,
+    branch: Option<String>,
+    access: ReadDocuments,
+    mut db: StupidDb,
+    settings: State<SharedConfig>,
+) -> Result<Content<Stream<ChangeFeed>>, StupidDbError> {
+    let limit = settings.read().unwrap().limits.change_feeds;
+    let slot = events::Slot::take(&FEEDS, limit);
+    if slot.is_none() {
+        return Err(StupidDbError::TooManyWatchers);
+    }
+
+    let repo = db.open(&repository);
+    if repo.is_err() {
+        return Err(repo.err().unwrap());
+    }
+
+    let repo = repo.unwrap();
+    let reference = match branch {
+        Some(b) => format!("refs/heads/{}", b),
+        None => repo
+            .head_branch()
+            .unwrap_or(String::from("refs/heads/master")),
+    };
+
+    // Subscribe before replaying, commits made in between show up in both
+    // and are skipped on the live side.
+    let receiver = events::subscribe();
+    let replay = repo.changes_since(reference.as_str(), since.as_ref().map(|s| s.as_str()));
+    if replay.is_err() {
+        return Err(replay.err().unwrap());
+    }
+
+    let replay: VecDeque<StupidChangeEvent> = replay.unwrap().into_iter().collect();
+    let feed = ChangeFeed {
+        repository: repo.name(),
+        reference,
+        seen: replay.iter().map(|e| e.commit.clone()).collect(),
+        replay,
+        receiver,
+        pending: Vec::new(),
+        flush: false,
+        access: access.0,
+        _slot: slot.unwrap(),
+    };
+
+    Ok(Content(
+        ContentType::new("text", "event-stream"),
+        Stream::chunked(feed, CHUNK_SIZE),
+    ))
+}
+
+#[derive(Debug)]
+pub struct ChangeFeed {
+    repository: String,
+    reference: String,
+    replay: VecDeque<StupidChangeEvent>,
+    seen: HashSet<String>,
+    receiver: Receiver<StupidChangeEvent>,
+    pending: Vec<u8>,
+    flush: bool,
+    access: DocumentAccess,
+    /// Given back when the client goes away and the feed is dropped
+    _slot: events::Slot,
+}
+
+impl ChangeFeed {
+    /// Next message to send, a keep-alive comment while nothing happens
+    fn next_message(&mut self) -> Option<String> {
+        while let Some(event) = self.replay.pop_front() {
+            if let Some(event) = self.visible(event) {
+                return Some(message(&event));
+            }
+        }
+
+        loop {
+            match self
+                .receiver
+                .recv_timeout(Duration::from_secs(KEEP_ALIVE_SECONDS))
+            {
+                Ok(event) => {
+                    if event.repository != self.repository
+                        || event.reference.as_ref() != Some(&self.reference)
+                        || self.seen.remove(&event.commit)
+                    {
+                        continue;
+                    }
+                    if let Some(event) = self.visible(event) {
+                        return Some(message(&event));
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => return Some(String::from(": keep-alive\n\n")),
+                Err(RecvTimeoutError::Disconnected) => return None,
+            }
+        }
+    }
+
+    /// The event with only the changes the token may read, None when it
+    /// changed nothing but documents the token may not read
+    fn visible(&self, mut event: StupidChangeEvent) -> Option<StupidChangeEvent> {
+        let length = event.changes.len();
+        event
+            .changes
+            .retain(|c| self.access.allows(c.name.as_str()));
+        match length > 0 && event.changes.is_empty() {
+            true => None,
+            false => Some(event),
+        }
+    }
+}
+
+impl Read for ChangeFeed {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            // Each message is flushed to the client before waiting for the next
+            if self.flush {
+                self.flush = false;
+                return Err(io::Error::from(io::ErrorKind::WouldBlock));
+            }
+
+            match self.next_message() {
+                Some(m) => self.pending = m.into_bytes(),
+                None => return Ok(0),
+            }
+        }
+
+        let length = buf.len().min(self.pending.len());
+        buf[..length].copy_from_slice(&self.pending[..length]);
+        self.pending.drain(..length);
+        self.flush = self.pending.is_empty();
+
+        Ok(length)
+    }
+}
+
+fn message(event: &StupidChangeEvent) -> String {
+    format!(
+        "id: {}\nevent: commit\ndata: {}\n\n",
+        event.commit,
+        serde_json::to_string(event).unwrap_or_default()
+    )
+}
@@ -1,40 +1,206 @@
+use rocket::State;
 use rocket_contrib::json::Json;
 
+use std::sync::atomic::AtomicUsize;
+use std::time::{Duration, Instant};
+
+use super::auth::{ReadDocuments, WriteDocuments};
+use crate::stupiddb::config::SharedConfig;
 use crate::stupiddb::error::StupidDbError;
+use crate::stupiddb::events;
 use crate::stupiddb::models;
 use crate::stupiddb::StupidDb;
 
-#[post("/<repository>", data = "<blob>")]
+const DEFAULT_WAIT: Duration = Duration::from_secs(30);
+
+/// Watches on documents waiting right now
+static WATCHERS: AtomicUsize = AtomicUsize::new(0);
+
+#[post("/<repository>?<branch>", data = "<blob>")]
 pub fn post(
     repository: String,
+    branch: Option<String>,
     blob: models::StupidBlob,
+    access: WriteDocuments,
+    mut db: StupidDb,
 ) -> Result<Json<models::StupidBlob>, StupidDbError> {
-    let mut db = StupidDb::new();
-    let open_error = db.open(&repository).err();
-    if open_error.is_some() {
-        return Err(open_error.unwrap());
+    let allowed = access.check(blob.name.as_str());
+    if allowed.is_err() {
+        return Err(allowed.err().unwrap());
+    }
+
+    let open_result = db.open(&repository);
+    if open_result.is_err() {
+        return Err(open_result.err().unwrap());
+    }
+
+    db.write(&blob, branch.as_ref().map(|b| b.as_str()))
+        .map(|b| Json(b))
+}
+
+/// Ranked after static routes such as `DELETE /_tokens/<id>`, which it
+/// would shadow otherwise
+#[delete("/<repository>/<name>?<message>", rank = 10)]
+pub fn delete(
+    repository: String,
+    name: String,
+    message: Option<String>,
+    access: WriteDocuments,
+    mut db: StupidDb,
+) -> Result<(), StupidDbError> {
+    let allowed = access.check(name.as_str());
+    if allowed.is_err() {
+        return Err(allowed.err().unwrap());
+    }
+
+    let open_result = db.open(&repository);
+    if open_result.is_err() {
+        return Err(open_result.err().unwrap());
+    }
+
+    db.remove(name.as_str(), message.as_ref().map(|m| m.as_str()))
+}
+
+/// Names of the documents at a revision the token may read
+#[get(
+    "/<repository>/_documents?<branch>&<tag>&<rev>",
+    format = "application/json"
+)]
+pub fn list(
+    repository: String,
+    branch: Option<String>,
+    tag: Option<String>,
+    rev: Option<String>,
+    access: ReadDocuments,
+    mut db: StupidDb,
+) -> Result<Json<Vec<String>>, StupidDbError> {
+    let open_result = db.open(&repository);
+    if open_result.is_err() {
+        return Err(open_result.err().unwrap());
+    }
+
+    let revision = revision(rev, tag, branch);
+    open_result
+        .unwrap()
+        .documents(revision.as_ref().map(|r| r.as_str()))
+        .map(|names| Json(names.into_iter().filter(|n| access.allows(n)).collect()))
+}
+
+/// A document, or nothing when a watched document did not change in time
+#[derive(Responder)]
+pub enum Watched {
+    Changed(Json<models::StupidBlob>),
+    #[response(status = 304)]
+    NotModified(()),
+}
+
+/// A document at HEAD, a branch, a tag or a revision.
+///
+/// With `wait` set to the id the client has, the request waits until the
+/// document on the ref read changes or `timeout` passes, answering 304 in
+/// the latter case. Each waiting request holds a worker, so at most
+/// `limits.watchers` wait at the same time and further ones get a 503.
+#[get(
+    "/<repository>/<name>?<branch>&<tag>&<rev>&<wait>&<timeout>",
+    format = "application/json",
+    rank = 10
+)]
+pub fn get(
+    repository: String,
+    name: String,
+    branch: Option<String>,
+    tag: Option<String>,
+    rev: Option<String>,
+    wait: Option<String>,
+    timeout: Option<String>,
+    access: ReadDocuments,
+    mut db: StupidDb,
+    settings: State<SharedConfig>,
+) -> Result<Watched, StupidDbError> {
+    let allowed = access.check(name.as_str());
+    if allowed.is_err() {
+        return Err(allowed.err().unwrap());
     }
 
-    let hashed_blob = db.hash_object_write(&blob);
-    if hashed_blob.is_err() {
-        return Err(hashed_blob.err().unwrap());
+    let open_result = db.open(&repository);
+    if open_result.is_err() {
+        return Err(open_result.err().unwrap());
     }
 
-    let update_error = db.update_index(hashed_blob.as_ref().unwrap()).err();
-    if update_error.is_some() {
-        return Err(update_error.unwrap());
+    let revision = revision(rev, tag, branch);
+    let repo = open_result.unwrap();
+    let read = || repo.read(name.as_str(), revision.as_ref().map(|r| r.as_str()));
+    if wait.is_none() {
+        return read().map(|b| Watched::Changed(Json(b)));
     }
 
-    let write_tree_result = db.write_tree();
-    if write_tree_result.is_err() {
-        return Err(write_tree_result.err().unwrap());
+    let max_wait = Duration::from_secs(settings.read().unwrap().limits.watch_timeout);
+    let timeout = match timeout {
+        None => Some(DEFAULT_WAIT.min(max_wait)),
+        Some(t) => parse_timeout(t.as_str()).filter(|t| *t <= max_wait),
+    };
+    if timeout.is_none() {
+        return Err(StupidDbError::TimeoutInvalid);
     }
 
-    let commit_error = db.commit_tree(write_tree_result.unwrap(), &blob).err();
+    let limit = settings.read().unwrap().limits.watchers;
+    let slot = events::Slot::take(&WATCHERS, limit);
+    if slot.is_none() {
+        return Err(StupidDbError::TooManyWatchers);
+    }
+
+    // A revision naming a commit never moves, one naming a branch does
+    let reference = match revision.as_ref() {
+        None => repo.head_branch().unwrap_or_default(),
+        Some(r) if r.starts_with("refs/") => r.to_string(),
+        Some(r) => format!("refs/heads/{}", r),
+    };
+
+    // Subscribed before reading, so a commit right after the read is not lost
+    let receiver = events::subscribe();
+    let deadline = Instant::now() + timeout.unwrap();
+    loop {
+        let blob = read();
+        if blob.is_err() || blob.as_ref().unwrap().id != wait {
+            return blob.map(|b| Watched::Changed(Json(b)));
+        }
+
+        let changed = events::wait_for_change(
+            &receiver,
+            repo.name().as_str(),
+            reference.as_str(),
+            name.as_str(),
+            deadline,
+        );
+        if !changed {
+            return Ok(Watched::NotModified(()));
+        }
+    }
+}
 
-    if commit_error.is_some() {
-        return Err(commit_error.unwrap());
+/// Revision a read refers to, a revision wins over a tag and a tag over a
+/// branch
+pub(super) fn revision(
+    rev: Option<String>,
+    tag: Option<String>,
+    branch: Option<String>,
+) -> Option<String> {
+    match (rev, tag, branch) {
+        (Some(r), _, _) => Some(r),
+        (None, Some(t), _) => Some(format!("refs/tags/{}", t)),
+        (None, None, Some(b)) => Some(format!("refs/heads/{}", b)),
+        (None, None, None) => None,
     }
+}
 
-    Ok(Json(hashed_blob.unwrap()))
+/// Parses durations like `30s`, `500ms` or `2m`, plain numbers are seconds
+fn parse_timeout(timeout: &str) -> Option<Duration> {
+    let digits = timeout.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let value = digits.parse::<u64>().ok()?;
+    match &timeout[digits.len()..] {
+        "" | "s" => Some(Duration::from_secs(value)),
+        "ms" => Some(Duration::from_millis(value)),
+        "m" => Some(Duration::from_secs(value * 60)),
+        _ => None,
+    }
 }
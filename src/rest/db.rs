@@ -0,0 +1,17 @@
+use rocket::request::{self, FromRequest, Request};
+use rocket::State;
+
+use crate::stupiddb::cache::SharedCache;
+use crate::stupiddb::config::SharedConfig;
+use crate::stupiddb::StupidDb;
+
+/// Hands handlers a StupidDb working with the managed config and cache
+impl<'a, 'r> FromRequest<'a, 'r> for StupidDb {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<StupidDb, ()> {
+        let config = request.guard::<State<SharedConfig>>();
+        let cache = request.guard::<State<SharedCache>>();
+        config.and_then(|config| cache.map(|cache| StupidDb::new(config.inner(), cache.inner())))
+    }
+}
@@ -0,0 +1,55 @@
+use rocket_contrib::json::Json;
+
+use super::auth::{AdminAccess, ReadAccess};
+use crate::stupiddb::encryption;
+use crate::stupiddb::error::StupidDbError;
+use crate::stupiddb::models::{StupidEncryptionConfig, StupidRotationResult};
+use crate::stupiddb::StupidDb;
+
+#[get("/<name>/encryption", format = "application/json")]
+pub fn get(
+    name: String,
+    _access: ReadAccess,
+    mut db: StupidDb,
+) -> Result<Json<StupidEncryptionConfig>, StupidDbError> {
+    let repository = db.open(&name);
+    if repository.is_err() {
+        return Err(repository.err().unwrap());
+    }
+
+    encryption::config(repository.unwrap()).map(|c| Json(c))
+}
+
+/// Sets the encryption rules, documents already stored follow them after
+/// a rotation
+#[put("/<name>/encryption", format = "application/json", data = "<config>")]
+pub fn put(
+    name: String,
+    config: StupidEncryptionConfig,
+    _access: AdminAccess,
+    mut db: StupidDb,
+) -> Result<Json<StupidEncryptionConfig>, StupidDbError> {
+    let repository = db.open(&name);
+    if repository.is_err() {
+        return Err(repository.err().unwrap());
+    }
+
+    encryption::set_config(repository.unwrap(), &config).map(|c| Json(c))
+}
+
+/// Encrypts the documents at HEAD again with the current key, as a single
+/// commit
+#[post("/<name>/encryption/rotate?<message>")]
+pub fn rotate(
+    name: String,
+    message: Option<String>,
+    _access: AdminAccess,
+    mut db: StupidDb,
+) -> Result<Json<StupidRotationResult>, StupidDbError> {
+    let repository = db.open(&name);
+    if repository.is_err() {
+        return Err(repository.err().unwrap());
+    }
+
+    encryption::rotate(repository.unwrap(), message.as_ref().map(|m| m.as_str())).map(|r| Json(r))
+}
@@ -0,0 +1,40 @@
+use crate::stupiddb::error::StupidDbError;
+use rocket::http::Status;
+use rocket::response::{self, Responder};
+use rocket::Request;
+use rocket_contrib::json::Json;
+
+impl<'r> Responder<'r> for StupidDbError {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let status = match self {
+            StupidDbError::RepoPathInvalid => Status::NotFound,
+            StupidDbError::RemoteNotFound => Status::NotFound,
+            StupidDbError::NoOriginRemote => Status::NotFound,
+            StupidDbError::DocumentNotFound => Status::NotFound,
+            StupidDbError::RevisionNotFound => Status::NotFound,
+            StupidDbError::BranchNotFound => Status::NotFound,
+            StupidDbError::WebhookNotFound => Status::NotFound,
+            StupidDbError::TokenNotFound => Status::NotFound,
+            StupidDbError::RemoteExists => Status::Conflict,
+            StupidDbError::BranchExists => Status::Conflict,
+            StupidDbError::BranchIsHead => Status::Conflict,
+            StupidDbError::TagExists => Status::Conflict,
+            StupidDbError::RepoIsMirror => Status::Conflict,
+            StupidDbError::RepoExists => Status::Conflict,
+            StupidDbError::EncryptionKeyNotFound => Status::Conflict,
+            StupidDbError::ConfirmationRequired => Status::BadRequest,
+            StupidDbError::TimeoutInvalid => Status::BadRequest,
+            StupidDbError::PathInvalid => Status::BadRequest,
+            StupidDbError::ContentInvalid => Status::BadRequest,
+            StupidDbError::TooManyWatchers => Status::ServiceUnavailable,
+            StupidDbError::Unauthorized => Status::Unauthorized,
+            StupidDbError::Forbidden => Status::Forbidden,
+            _ => Status::InternalServerError,
+        };
+
+        let body = json!({ "error": format!("{:?}", self) });
+        response::Response::build_from(Json(body).respond_to(request)?)
+            .status(status)
+            .ok()
+    }
+}
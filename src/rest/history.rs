@@ -0,0 +1,80 @@
+use rocket_contrib::json::Json;
+
+use super::auth::ReadDocuments;
+use super::data::revision;
+use crate::stupiddb::error::StupidDbError;
+use crate::stupiddb::models::{StupidChangeEvent, StupidPickaxeHit, StupidPickaxeQuery};
+use crate::stupiddb::StupidDb;
+
+/// Commits returned when no limit is given, each one has its signature
+/// checked
+const DEFAULT_LIMIT: usize = 100;
+
+/// Commits of a revision with their signatures checked, newest first.
+/// Commits that only changed documents the token may not read are left
+/// out, as are those changes.
+#[get(
+    "/<repository>/_log?<branch>&<tag>&<rev>&<limit>",
+    format = "application/json"
+)]
+pub fn log(
+    repository: String,
+    branch: Option<String>,
+    tag: Option<String>,
+    rev: Option<String>,
+    limit: Option<usize>,
+    access: ReadDocuments,
+    mut db: StupidDb,
+) -> Result<Json<Vec<StupidChangeEvent>>, StupidDbError> {
+    let open_result = db.open(&repository);
+    if open_result.is_err() {
+        return Err(open_result.err().unwrap());
+    }
+
+    let revision = revision(rev, tag, branch);
+    open_result
+        .unwrap()
+        .log(
+            revision.as_ref().map(|r| r.as_str()),
+            Some(limit.unwrap_or(DEFAULT_LIMIT)),
+            &|n| access.allows(n),
+        )
+        .map(|events| Json(events))
+}
+
+/// Commits that changed the value at a path, `value` being JSON text or
+/// else taken as a string
+#[get(
+    "/<repository>/_history?<path>&<value>&<prefix>&<from>&<to>&<limit>",
+    format = "application/json"
+)]
+pub fn pickaxe(
+    repository: String,
+    path: String,
+    value: Option<String>,
+    prefix: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    limit: Option<usize>,
+    access: ReadDocuments,
+    mut db: StupidDb,
+) -> Result<Json<Vec<StupidPickaxeHit>>, StupidDbError> {
+    let open_result = db.open(&repository);
+    if open_result.is_err() {
+        return Err(open_result.err().unwrap());
+    }
+
+    let query = StupidPickaxeQuery {
+        path,
+        value: value
+            .map(|v| serde_json::from_str(v.as_str()).unwrap_or(serde_json::Value::String(v))),
+        prefix,
+        from,
+        to,
+        limit: Some(limit.unwrap_or(DEFAULT_LIMIT)),
+    };
+    open_result
+        .unwrap()
+        .pickaxe(&query, &|n| access.allows(n))
+        .map(|hits| Json(hits))
+}
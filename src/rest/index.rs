@@ -0,0 +1,57 @@
+use rocket_contrib::json::Json;
+
+use super::auth::{AdminAccess, ReadAccess, WriteAccess};
+use crate::stupiddb::error::StupidDbError;
+use crate::stupiddb::index;
+use crate::stupiddb::models::{StupidIndexConfig, StupidIndexStatus};
+use crate::stupiddb::StupidDb;
+
+/// Status of the index of a branch, of the one HEAD points at by default
+#[get("/<name>/indexes?<branch>", format = "application/json")]
+pub fn get(
+    name: String,
+    branch: Option<String>,
+    _access: ReadAccess,
+    mut db: StupidDb,
+) -> Result<Json<StupidIndexStatus>, StupidDbError> {
+    let repository = db.open(&name);
+    if repository.is_err() {
+        return Err(repository.err().unwrap());
+    }
+
+    let reference = branch.map(|b| format!("refs/heads/{}", b));
+    index::status(repository.unwrap(), reference.as_ref().map(|r| r.as_str())).map(|s| Json(s))
+}
+
+#[put("/<name>/indexes", format = "application/json", data = "<config>")]
+pub fn put(
+    name: String,
+    config: StupidIndexConfig,
+    _access: AdminAccess,
+    mut db: StupidDb,
+) -> Result<Json<StupidIndexStatus>, StupidDbError> {
+    let repository = db.open(&name);
+    if repository.is_err() {
+        return Err(repository.err().unwrap());
+    }
+
+    index::set_paths(repository.unwrap(), &config.paths).map(|s| Json(s))
+}
+
+/// Builds the index of a branch again from its commit, of the one HEAD
+/// points at by default
+#[post("/<name>/indexes/rebuild?<branch>")]
+pub fn rebuild(
+    name: String,
+    branch: Option<String>,
+    _access: WriteAccess,
+    mut db: StupidDb,
+) -> Result<Json<StupidIndexStatus>, StupidDbError> {
+    let repository = db.open(&name);
+    if repository.is_err() {
+        return Err(repository.err().unwrap());
+    }
+
+    let reference = branch.map(|b| format!("refs/heads/{}", b));
+    index::rebuild(repository.unwrap(), reference.as_ref().map(|r| r.as_str())).map(|s| Json(s))
+}
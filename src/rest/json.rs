@@ -1,8 +1,20 @@
 use crate::stupiddb::models::StupidBlob;
+use crate::stupiddb::models::StupidBranchRequest;
 use crate::stupiddb::models::StupidDbRepositoryConfig;
+use crate::stupiddb::models::StupidEncryptionConfig;
+use crate::stupiddb::models::StupidIndexConfig;
+use crate::stupiddb::models::StupidMergeRequest;
+use crate::stupiddb::models::StupidPushRequest;
+use crate::stupiddb::models::StupidQuery;
+use crate::stupiddb::models::StupidRemote;
+use crate::stupiddb::models::StupidRenameRequest;
+use crate::stupiddb::models::StupidTagRequest;
+use crate::stupiddb::models::StupidToken;
+use crate::stupiddb::models::StupidWebhook;
 use rocket::data::{self, FromDataSimple};
 use rocket::http::Status;
 use rocket::{Data, Outcome::*, Request};
+use serde::de::DeserializeOwned;
 use std::io::Read;
 use validator::Validate;
 
@@ -11,52 +23,142 @@ const LIMIT: u64 = 1 << 20;
 impl FromDataSimple for StupidBlob {
     type Error = ValidationError;
     fn from_data(request: &Request, data: Data) -> data::Outcome<Self, Self::Error> {
-        let size_limit = request.limits().get("json").unwrap_or(LIMIT);
-        let mut string = String::new().to_owned();
-        if let Err(_) = data.open().take(size_limit).read_to_string(&mut string) {
-            return Failure((Status::BadRequest, ValidationError::BadFormat));
-        }
+        from_json(request, data, ValidationError::BadFormat)
+    }
+}
 
-        let deserialized: Option<Self> = match serde_json::from_str(string.as_str()) {
-            Ok(c) => Some(c),
-            Err(_) => None,
-        };
+impl FromDataSimple for StupidDbRepositoryConfig {
+    type Error = ValidationError;
+    fn from_data(request: &Request, data: Data) -> data::Outcome<Self, Self::Error> {
+        from_json(request, data, ValidationError::BadValue)
+    }
+}
 
-        if deserialized.is_none() {
-            return Failure((Status::BadRequest, ValidationError::BadFormat));
-        }
+impl FromDataSimple for StupidBranchRequest {
+    type Error = ValidationError;
+    fn from_data(request: &Request, data: Data) -> data::Outcome<Self, Self::Error> {
+        from_json(request, data, ValidationError::BadValue)
+    }
+}
 
-        let t = deserialized.unwrap();
-        match t.validate() {
-            Ok(_) => Success(t),
-            Err(_) => Failure((Status::BadRequest, ValidationError::BadFormat)),
-        }
+impl FromDataSimple for StupidMergeRequest {
+    type Error = ValidationError;
+    fn from_data(request: &Request, data: Data) -> data::Outcome<Self, Self::Error> {
+        from_json(request, data, ValidationError::BadValue)
     }
 }
 
-impl FromDataSimple for StupidDbRepositoryConfig {
+impl FromDataSimple for StupidRemote {
     type Error = ValidationError;
     fn from_data(request: &Request, data: Data) -> data::Outcome<Self, Self::Error> {
-        let size_limit = request.limits().get("json").unwrap_or(LIMIT);
-        let mut string = String::new().to_owned();
-        if let Err(_) = data.open().take(size_limit).read_to_string(&mut string) {
-            return Failure((Status::BadRequest, ValidationError::BadFormat));
-        }
+        from_json(request, data, ValidationError::BadValue)
+    }
+}
+
+impl FromDataSimple for StupidRenameRequest {
+    type Error = ValidationError;
+    fn from_data(request: &Request, data: Data) -> data::Outcome<Self, Self::Error> {
+        from_json(request, data, ValidationError::BadValue)
+    }
+}
+
+impl FromDataSimple for StupidTagRequest {
+    type Error = ValidationError;
+    fn from_data(request: &Request, data: Data) -> data::Outcome<Self, Self::Error> {
+        from_json(request, data, ValidationError::BadValue)
+    }
+}
+
+impl FromDataSimple for StupidIndexConfig {
+    type Error = ValidationError;
+    fn from_data(request: &Request, data: Data) -> data::Outcome<Self, Self::Error> {
+        from_json(request, data, ValidationError::BadValue)
+    }
+}
 
-        let deserialized: Option<Self> = match serde_json::from_str(string.as_str()) {
-            Ok(c) => Some(c),
-            Err(_) => None,
+impl FromDataSimple for StupidEncryptionConfig {
+    type Error = ValidationError;
+    fn from_data(request: &Request, data: Data) -> data::Outcome<Self, Self::Error> {
+        from_json(request, data, ValidationError::BadValue)
+    }
+}
+
+impl FromDataSimple for StupidQuery {
+    type Error = ValidationError;
+    fn from_data(request: &Request, data: Data) -> data::Outcome<Self, Self::Error> {
+        from_json(request, data, ValidationError::BadValue)
+    }
+}
+
+impl FromDataSimple for StupidWebhook {
+    type Error = ValidationError;
+    fn from_data(request: &Request, data: Data) -> data::Outcome<Self, Self::Error> {
+        from_json(request, data, ValidationError::BadValue)
+    }
+}
+
+impl FromDataSimple for StupidToken {
+    type Error = ValidationError;
+    fn from_data(request: &Request, data: Data) -> data::Outcome<Self, Self::Error> {
+        from_json(request, data, ValidationError::BadValue)
+    }
+}
+
+impl FromDataSimple for StupidPushRequest {
+    type Error = ValidationError;
+    fn from_data(request: &Request, data: Data) -> data::Outcome<Self, Self::Error> {
+        let string = match read_body(request, data) {
+            Ok(s) => s,
+            Err(e) => return Failure((Status::BadRequest, e)),
         };
 
-        if deserialized.is_none() {
-            return Failure((Status::BadRequest, ValidationError::BadFormat));
+        // A push without body pushes what the repository is configured to
+        if string.trim().is_empty() {
+            return Success(StupidPushRequest::default());
         }
 
-        let t = deserialized.unwrap();
-        match t.validate() {
-            Ok(_) => Success(t),
-            Err(_) => Failure((Status::BadRequest, ValidationError::BadValue)),
-        }
+        parse(string, ValidationError::BadValue)
+    }
+}
+
+fn from_json<T: DeserializeOwned + Validate>(
+    request: &Request,
+    data: Data,
+    invalid: ValidationError,
+) -> data::Outcome<T, ValidationError> {
+    match read_body(request, data) {
+        Ok(s) => parse(s, invalid),
+        Err(e) => Failure((Status::BadRequest, e)),
+    }
+}
+
+fn read_body(request: &Request, data: Data) -> Result<String, ValidationError> {
+    let size_limit = request.limits().get("json").unwrap_or(LIMIT);
+    let mut string = String::new().to_owned();
+    if let Err(_) = data.open().take(size_limit).read_to_string(&mut string) {
+        return Err(ValidationError::BadFormat);
+    }
+
+    Ok(string)
+}
+
+fn parse<T: DeserializeOwned + Validate>(
+    string: String,
+    invalid: ValidationError,
+) -> data::Outcome<T, ValidationError> {
+    let deserialized: Option<T> = match serde_json::from_str(string.as_str()) {
+        Ok(c) => Some(c),
+        Err(_) => None,
+    };
+
+    if deserialized.is_none() {
+        return Failure((Status::BadRequest, ValidationError::BadFormat));
+    }
+
+    let t = deserialized.unwrap();
+    match t.validate() {
+        Ok(_) => Success(t),
+        Err(_) => Failure((Status::BadRequest, invalid)),
     }
 }
 
@@ -1,3 +1,18 @@
+mod auth;
+pub mod branch;
+pub mod changes;
 pub mod data;
+mod db;
+pub mod encryption;
+mod error;
+pub mod history;
+pub mod index;
 mod json;
+pub mod query;
+pub mod remote;
 pub mod repository;
+pub mod revert;
+pub mod search;
+pub mod tag;
+pub mod token;
+pub mod webhook;
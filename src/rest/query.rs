@@ -0,0 +1,35 @@
+use rocket_contrib::json::Json;
+
+use super::auth::ReadDocuments;
+use super::data::revision;
+use crate::stupiddb::error::StupidDbError;
+use crate::stupiddb::models::{StupidQuery, StupidQueryHit};
+use crate::stupiddb::StupidDb;
+
+#[post(
+    "/<repository>/_query?<branch>&<tag>&<rev>",
+    format = "application/json",
+    data = "<query>"
+)]
+pub fn query(
+    repository: String,
+    branch: Option<String>,
+    tag: Option<String>,
+    rev: Option<String>,
+    query: StupidQuery,
+    access: ReadDocuments,
+    mut db: StupidDb,
+) -> Result<Json<Vec<StupidQueryHit>>, StupidDbError> {
+    let open_result = db.open(&repository);
+    if open_result.is_err() {
+        return Err(open_result.err().unwrap());
+    }
+
+    let revision = revision(rev, tag, branch);
+    open_result
+        .unwrap()
+        .query(&query, revision.as_ref().map(|r| r.as_str()), &|n| {
+            access.allows(n)
+        })
+        .map(|hits| Json(hits))
+}
@@ -0,0 +1,71 @@
+use rocket_contrib::json::Json;
+
+use super::auth::{AdminAccess, ReadAccess};
+use crate::stupiddb::error::StupidDbError;
+use crate::stupiddb::models::StupidRemote;
+use crate::stupiddb::StupidDb;
+
+#[get("/<name>/remotes", format = "application/json")]
+pub fn list(
+    name: String,
+    _access: ReadAccess,
+    mut db: StupidDb,
+) -> Result<Json<Vec<StupidRemote>>, StupidDbError> {
+    db.open(&name).map(|r| Json(r.remotes.clone()))
+}
+
+#[post("/<name>/remotes", format = "application/json", data = "<remote>")]
+pub fn post(
+    name: String,
+    remote: StupidRemote,
+    _access: AdminAccess,
+    mut db: StupidDb,
+) -> Result<Json<StupidRemote>, StupidDbError> {
+    let repository = db.open(&name);
+    if repository.is_err() {
+        return Err(repository.err().unwrap());
+    }
+
+    repository
+        .unwrap()
+        .add_remote(&remote)
+        .map(|_| Json(remote))
+}
+
+#[put(
+    "/<name>/remotes/<remote_name>",
+    format = "application/json",
+    data = "<remote>"
+)]
+pub fn put(
+    name: String,
+    remote_name: String,
+    remote: StupidRemote,
+    _access: AdminAccess,
+    mut db: StupidDb,
+) -> Result<Json<StupidRemote>, StupidDbError> {
+    let repository = db.open(&name);
+    if repository.is_err() {
+        return Err(repository.err().unwrap());
+    }
+
+    repository
+        .unwrap()
+        .update_remote(remote_name.as_str(), &remote)
+        .map(|_| Json(remote))
+}
+
+#[delete("/<name>/remotes/<remote_name>")]
+pub fn delete(
+    name: String,
+    remote_name: String,
+    _access: AdminAccess,
+    mut db: StupidDb,
+) -> Result<(), StupidDbError> {
+    let repository = db.open(&name);
+    if repository.is_err() {
+        return Err(repository.err().unwrap());
+    }
+
+    repository.unwrap().remove_remote(remote_name.as_str())
+}
@@ -1,19 +1,34 @@
 use rocket_contrib::json::Json;
 
+use super::auth::{AdminAccess, Authenticated, PushAccess, ReadAccess, ServerAdmin};
 use crate::stupiddb::error::StupidDbError;
-use crate::stupiddb::models::StupidDbRepositoryConfig;
+use crate::stupiddb::info::{StupidRepositoryInfo, StupidRepositorySummary};
+use crate::stupiddb::mirror;
+use crate::stupiddb::models::{
+    StupidDbRepositoryConfig, StupidPushRequest, StupidRemotePushResult, StupidRenameRequest,
+};
+use crate::stupiddb::repository::DEFAULT_REMOTE;
 use crate::stupiddb::StupidDb;
 
 #[post("/", format = "application/json", data = "<config>")]
 pub fn post(
     config: StupidDbRepositoryConfig,
+    _access: ServerAdmin,
+    mut db: StupidDb,
 ) -> Result<Json<StupidDbRepositoryConfig>, StupidDbError> {
-    let mut db = StupidDb::new();
     let result = db.init(&config);
     if result.is_err() {
         return Err(result.err().unwrap());
     }
 
+    if config.credentials.is_some() {
+        let credentials_result =
+            db.set_credentials(&config.name, config.credentials.as_ref().unwrap());
+        if credentials_result.is_err() {
+            return Err(credentials_result.err().unwrap());
+        }
+    }
+
     let open_result = db.open(&config.name);
     if open_result.is_err() {
         return Err(open_result.err().unwrap());
@@ -26,7 +41,7 @@ pub fn post(
     match (repo_origin, config_origin) {
         (r, Some(co)) => {
             if r.is_none() || !r.unwrap().eq(co) {
-                let set_remote_result = repo.set_remoteurl("origin", co.as_str());
+                let set_remote_result = repo.set_remoteurl(DEFAULT_REMOTE, co.as_str());
                 if set_remote_result.is_err() {
                     return Err(set_remote_result.err().unwrap());
                 }
@@ -35,36 +50,120 @@ pub fn post(
         (_, None) => (),
     }
 
+    if config.push_refspecs.is_some() {
+        let refspecs_result =
+            repo.set_push_refspecs(DEFAULT_REMOTE, config.push_refspecs.as_ref().unwrap());
+        if refspecs_result.is_err() {
+            return Err(refspecs_result.err().unwrap());
+        }
+    }
+
+    let mirror_result = repo.set_mirror(config.mirror.as_ref());
+    if mirror_result.is_err() {
+        return Err(mirror_result.err().unwrap());
+    }
+
+    for remote in config.remotes.iter() {
+        let remote_result = match repo.remote(remote.name.as_str()) {
+            Some(_) => repo.update_remote(remote.name.as_str(), remote),
+            None => repo.add_remote(remote),
+        };
+        if remote_result.is_err() {
+            return Err(remote_result.err().unwrap());
+        }
+    }
+
     Ok(Json(config))
 }
 
+#[get("/", format = "application/json")]
+pub fn list(access: Authenticated, db: StupidDb) -> Json<Vec<StupidRepositorySummary>> {
+    Json(
+        db.list()
+            .into_iter()
+            .filter(|r| access.can_read(r.name.as_str()))
+            .collect(),
+    )
+}
+
 #[get("/<name>", format = "application/json")]
-pub fn get(name: String) -> Result<Json<StupidDbRepositoryConfig>, StupidDbError> {
-    let mut db = StupidDb::new();
+pub fn get(
+    name: String,
+    _access: ReadAccess,
+    mut db: StupidDb,
+) -> Result<Json<StupidDbRepositoryConfig>, StupidDbError> {
     db.open(&name).map(|r| {
         let origin = match r.origin.as_ref() {
             Some(o) => Some(String::from(o.as_str())),
             None => None,
         };
-        Json(StupidDbRepositoryConfig { name, origin })
+        Json(StupidDbRepositoryConfig {
+            name,
+            origin,
+            credentials: None,
+            push_refspecs: r
+                .remote(DEFAULT_REMOTE)
+                .and_then(|o| o.push_refspecs.clone()),
+            remotes: r.remotes.clone(),
+            mirror: r.mirror.clone(),
+            mirror_status: mirror::status(r),
+        })
     })
 }
 
-#[post("/<name>/push")]
-pub fn push(name: String) -> rocket::response::status::Accepted<String> {
-    let name_copy = name.clone();
-    std::thread::spawn(|| {
-        let name_in_thread = name_copy;
-        let mut db = StupidDb::new();
-        let repository = db.open(&name_in_thread);
-        if repository.is_err() {
-            return;
-        }
-        match repository.as_ref().unwrap().push() {
-            Ok(_) => println!("Repository was pushed. We can notify services."),
-            Err(_) => println!("Error occured."),
-        }
-    });
+#[delete("/<name>?<confirm>&<purge>")]
+pub fn delete(
+    name: String,
+    confirm: Option<bool>,
+    purge: Option<bool>,
+    _access: AdminAccess,
+    mut db: StupidDb,
+) -> Result<(), StupidDbError> {
+    if confirm != Some(true) {
+        return Err(StupidDbError::ConfirmationRequired);
+    }
+
+    db.delete(&name, purge.unwrap_or(false))
+}
+
+#[post("/<name>/rename", format = "application/json", data = "<request>")]
+pub fn rename(
+    name: String,
+    request: StupidRenameRequest,
+    _access: AdminAccess,
+    mut db: StupidDb,
+) -> Result<Json<StupidRenameRequest>, StupidDbError> {
+    db.rename(&name, &request.name).map(|_| Json(request))
+}
+
+#[get("/<name>/info", format = "application/json")]
+pub fn info(
+    name: String,
+    _access: ReadAccess,
+    mut db: StupidDb,
+) -> Result<Json<StupidRepositoryInfo>, StupidDbError> {
+    let repository = db.open(&name);
+    if repository.is_err() {
+        return Err(repository.err().unwrap());
+    }
+
+    repository.unwrap().info().map(|i| Json(i))
+}
+
+#[post("/<name>/push", data = "<request>")]
+pub fn push(
+    name: String,
+    request: StupidPushRequest,
+    _access: PushAccess,
+    mut db: StupidDb,
+) -> Result<Json<Vec<StupidRemotePushResult>>, StupidDbError> {
+    let repository = db.open(&name);
+    if repository.is_err() {
+        return Err(repository.err().unwrap());
+    }
 
-    rocket::response::status::Accepted(Some(name))
+    repository
+        .unwrap()
+        .push(&request)
+        .map(|results| Json(results))
 }
@@ -0,0 +1,65 @@
+use rocket::http::Status;
+use rocket::response::status;
+use rocket_contrib::json::Json;
+
+use super::auth::WriteDocuments;
+use crate::stupiddb::error::StupidDbError;
+use crate::stupiddb::models::StupidRevertResult;
+use crate::stupiddb::StupidDb;
+
+#[post("/<repository>/<name>/revert?<to>")]
+pub fn document(
+    repository: String,
+    name: String,
+    to: String,
+    access: WriteDocuments,
+    mut db: StupidDb,
+) -> Result<Json<StupidRevertResult>, StupidDbError> {
+    let allowed = access.check(name.as_str());
+    if allowed.is_err() {
+        return Err(allowed.err().unwrap());
+    }
+
+    let open_result = db.open(&repository);
+    if open_result.is_err() {
+        return Err(open_result.err().unwrap());
+    }
+
+    let repo = open_result.unwrap();
+    repo.revert_document(name.as_str(), to.as_str(), None)
+        .map(|oid| {
+            Json(StupidRevertResult {
+                commit: Some(oid.to_string()),
+                conflicts: Vec::new(),
+            })
+        })
+}
+
+#[post("/<repository>/revert/<commit>")]
+pub fn commit(
+    repository: String,
+    commit: String,
+    access: WriteDocuments,
+    mut db: StupidDb,
+) -> Result<status::Custom<Json<StupidRevertResult>>, StupidDbError> {
+    let open_result = db.open(&repository);
+    if open_result.is_err() {
+        return Err(open_result.err().unwrap());
+    }
+
+    let repo = open_result.unwrap();
+    // Every document the commit touched must be writable by the token
+    let names = repo.commit_documents(commit.as_str());
+    if names.is_err() {
+        return Err(names.err().unwrap());
+    }
+    if !names.unwrap().iter().all(|n| access.allows(n)) {
+        return Err(StupidDbError::Forbidden);
+    }
+
+    repo.revert_commit(commit.as_str(), None)
+        .map(|result| match result.commit {
+            None => status::Custom(Status::Conflict, Json(result)),
+            Some(_) => status::Custom(Status::Ok, Json(result)),
+        })
+}
@@ -0,0 +1,45 @@
+use rocket_contrib::json::Json;
+
+use super::auth::{ReadDocuments, WriteAccess};
+use crate::stupiddb::error::StupidDbError;
+use crate::stupiddb::models::StupidSearchHit;
+use crate::stupiddb::search;
+use crate::stupiddb::StupidDb;
+
+const DEFAULT_LIMIT: usize = 20;
+
+#[get("/<repository>/_search?<q>&<limit>", format = "application/json")]
+pub fn search(
+    repository: String,
+    q: String,
+    limit: Option<usize>,
+    access: ReadDocuments,
+    mut db: StupidDb,
+) -> Result<Json<Vec<StupidSearchHit>>, StupidDbError> {
+    let open_result = db.open(&repository);
+    if open_result.is_err() {
+        return Err(open_result.err().unwrap());
+    }
+
+    search::search(
+        open_result.unwrap(),
+        q.as_str(),
+        limit.unwrap_or(DEFAULT_LIMIT),
+        &|n| access.allows(n),
+    )
+    .map(|hits| Json(hits))
+}
+
+#[post("/<repository>/_search/rebuild")]
+pub fn rebuild(
+    repository: String,
+    _access: WriteAccess,
+    mut db: StupidDb,
+) -> Result<(), StupidDbError> {
+    let open_result = db.open(&repository);
+    if open_result.is_err() {
+        return Err(open_result.err().unwrap());
+    }
+
+    search::rebuild(open_result.unwrap())
+}
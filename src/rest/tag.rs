@@ -0,0 +1,35 @@
+use rocket_contrib::json::Json;
+
+use super::auth::{ReadAccess, WriteAccess};
+use crate::stupiddb::error::StupidDbError;
+use crate::stupiddb::models::{StupidTag, StupidTagRequest};
+use crate::stupiddb::StupidDb;
+
+#[get("/<name>/tags", format = "application/json")]
+pub fn list(
+    name: String,
+    _access: ReadAccess,
+    mut db: StupidDb,
+) -> Result<Json<Vec<StupidTag>>, StupidDbError> {
+    let repository = db.open(&name);
+    if repository.is_err() {
+        return Err(repository.err().unwrap());
+    }
+
+    repository.unwrap().tags().map(|t| Json(t))
+}
+
+#[post("/<name>/tags", format = "application/json", data = "<request>")]
+pub fn post(
+    name: String,
+    request: StupidTagRequest,
+    _access: WriteAccess,
+    mut db: StupidDb,
+) -> Result<Json<StupidTag>, StupidDbError> {
+    let repository = db.open(&name);
+    if repository.is_err() {
+        return Err(repository.err().unwrap());
+    }
+
+    repository.unwrap().create_tag(&request).map(|t| Json(t))
+}
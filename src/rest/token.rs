@@ -0,0 +1,38 @@
+use rocket::State;
+use rocket_contrib::json::Json;
+
+use super::auth::ServerAdmin;
+use crate::stupiddb::auth;
+use crate::stupiddb::config::SharedConfig;
+use crate::stupiddb::error::StupidDbError;
+use crate::stupiddb::models::StupidToken;
+
+#[get("/_tokens", format = "application/json")]
+pub fn list(
+    _access: ServerAdmin,
+    settings: State<SharedConfig>,
+) -> Result<Json<Vec<StupidToken>>, StupidDbError> {
+    let root_dir = settings.read().unwrap().root_dir.clone();
+    auth::list(root_dir.as_str()).map(|tokens| Json(tokens))
+}
+
+/// Creates a token, the response holds the only copy of its secret
+#[post("/_tokens", format = "application/json", data = "<token>")]
+pub fn post(
+    token: StupidToken,
+    _access: ServerAdmin,
+    settings: State<SharedConfig>,
+) -> Result<Json<StupidToken>, StupidDbError> {
+    let root_dir = settings.read().unwrap().root_dir.clone();
+    auth::create(root_dir.as_str(), &token).map(|t| Json(t))
+}
+
+#[delete("/_tokens/<id>")]
+pub fn delete(
+    id: String,
+    _access: ServerAdmin,
+    settings: State<SharedConfig>,
+) -> Result<(), StupidDbError> {
+    let root_dir = settings.read().unwrap().root_dir.clone();
+    auth::revoke(root_dir.as_str(), id.as_str())
+}
@@ -0,0 +1,73 @@
+use rocket_contrib::json::Json;
+
+use super::auth::AdminAccess;
+use crate::stupiddb::error::StupidDbError;
+use crate::stupiddb::models::{StupidDelivery, StupidWebhook};
+use crate::stupiddb::webhook;
+use crate::stupiddb::StupidDb;
+
+#[get("/<name>/webhooks", format = "application/json")]
+pub fn list(
+    name: String,
+    _access: AdminAccess,
+    mut db: StupidDb,
+) -> Result<Json<Vec<StupidWebhook>>, StupidDbError> {
+    let repository = db.open(&name);
+    if repository.is_err() {
+        return Err(repository.err().unwrap());
+    }
+
+    webhook::list(repository.unwrap()).map(|webhooks| {
+        Json(
+            webhooks
+                .into_iter()
+                .map(|w| StupidWebhook { secret: None, ..w })
+                .collect(),
+        )
+    })
+}
+
+#[post("/<name>/webhooks", format = "application/json", data = "<request>")]
+pub fn post(
+    name: String,
+    request: StupidWebhook,
+    _access: AdminAccess,
+    mut db: StupidDb,
+) -> Result<Json<StupidWebhook>, StupidDbError> {
+    let repository = db.open(&name);
+    if repository.is_err() {
+        return Err(repository.err().unwrap());
+    }
+
+    webhook::add(repository.unwrap(), &request).map(|w| Json(StupidWebhook { secret: None, ..w }))
+}
+
+#[delete("/<name>/webhooks/<id>")]
+pub fn delete(
+    name: String,
+    id: String,
+    _access: AdminAccess,
+    mut db: StupidDb,
+) -> Result<(), StupidDbError> {
+    let repository = db.open(&name);
+    if repository.is_err() {
+        return Err(repository.err().unwrap());
+    }
+
+    webhook::remove(repository.unwrap(), id.as_str())
+}
+
+#[get("/<name>/webhooks/<id>/deliveries", format = "application/json")]
+pub fn deliveries(
+    name: String,
+    id: String,
+    _access: AdminAccess,
+    mut db: StupidDb,
+) -> Result<Json<Vec<StupidDelivery>>, StupidDbError> {
+    let repository = db.open(&name);
+    if repository.is_err() {
+        return Err(repository.err().unwrap());
+    }
+
+    webhook::deliveries(repository.unwrap(), id.as_str()).map(|d| Json(d))
+}
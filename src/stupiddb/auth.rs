@@ -0,0 +1,273 @@
+//! Bearer tokens and the roles they grant.
+//!
+//! Tokens are kept in a file under the root dir, next to the repositories.
+//! Only the SHA-256 hash of a token is stored, the token itself is shown
+//! once when it is created.
+
+use super::error::StupidDbError;
+use super::models::{StupidRole, StupidToken, ALL_REPOSITORIES, DOCUMENTS_SEPARATOR};
+use lazy_static::lazy_static;
+use ring::digest;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+const TOKENS_FILE: &str = ".tokens.json";
+const SECRET_PREFIX: &str = "sdb_";
+/// Random bytes of a token
+const SECRET_LENGTH: usize = 32;
+
+lazy_static! {
+    static ref TOKENS_LOCK: Mutex<()> = Mutex::new(());
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredToken {
+    id: String,
+    name: String,
+    /// Hex encoded SHA-256 of the token
+    hash: String,
+    roles: HashMap<String, StupidRole>,
+    created: u64,
+}
+
+impl StoredToken {
+    fn token(&self) -> StupidToken {
+        StupidToken {
+            id: self.id.to_string(),
+            name: self.name.to_string(),
+            roles: self.roles.clone(),
+            created: self.created,
+            secret: None,
+        }
+    }
+}
+
+impl StupidToken {
+    /// Whether the token has the role, or a greater one, on a repository
+    /// as a whole
+    pub fn allows(&self, repository: &str, role: StupidRole) -> bool {
+        self.roles
+            .get(repository)
+            .into_iter()
+            .chain(self.roles.get(ALL_REPOSITORIES))
+            .any(|granted| *granted >= role)
+    }
+
+    /// Whether the token has the role on a document of a repository
+    pub fn allows_document(&self, repository: &str, name: &str, role: StupidRole) -> bool {
+        self.allows(repository, role)
+            || self
+                .document_roles(repository)
+                .any(|(pattern, granted)| granted >= role && matches(pattern, name))
+    }
+
+    /// Whether the token has the role on the repository or on any of its
+    /// documents
+    pub fn allows_some(&self, repository: &str, role: StupidRole) -> bool {
+        self.allows(repository, role)
+            || self
+                .document_roles(repository)
+                .any(|(_, granted)| granted >= role)
+    }
+
+    /// Document patterns of a repository with the roles on them
+    fn document_roles<'a>(
+        &'a self,
+        repository: &'a str,
+    ) -> impl Iterator<Item = (&'a str, StupidRole)> + 'a {
+        self.roles.iter().filter_map(move |(key, role)| {
+            let mut parts = key.splitn(2, DOCUMENTS_SEPARATOR);
+            let scope = parts.next().unwrap_or_default();
+            match parts.next() {
+                Some(p) if scope == repository || scope == ALL_REPOSITORIES => Some((p, *role)),
+                _ => None,
+            }
+        })
+    }
+}
+
+/// Whether a document name is the name of a pattern, or starts with its
+/// prefix followed by *
+pub fn matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => pattern == name,
+    }
+}
+
+/// The token a bearer token was created as, None when it is unknown
+pub fn authenticate(root_dir: &str, secret: &str) -> Option<StupidToken> {
+    let hash = hash(secret);
+    read(root_dir)
+        .ok()?
+        .iter()
+        .find(|t| t.hash == hash)
+        .map(|t| t.token())
+}
+
+pub fn list(root_dir: &str) -> Result<Vec<StupidToken>, StupidDbError> {
+    read(root_dir).map(|tokens| tokens.iter().map(|t| t.token()).collect())
+}
+
+/// Creates a token, the only time its secret is returned
+pub fn create(root_dir: &str, token: &StupidToken) -> Result<StupidToken, StupidDbError> {
+    let mut bytes = [0u8; SECRET_LENGTH];
+    if SystemRandom::new().fill(&mut bytes).is_err() {
+        return Err(StupidDbError::TokenStoreFailed);
+    }
+
+    let secret = format!("{}{}", SECRET_PREFIX, hex(&bytes));
+    let stored = StoredToken {
+        id: Uuid::new_v4().to_string(),
+        name: token.name.to_string(),
+        hash: hash(secret.as_str()),
+        roles: token.roles.clone(),
+        created: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs()),
+    };
+
+    let _guard = TOKENS_LOCK.lock().unwrap();
+    let tokens = read(root_dir);
+    if tokens.is_err() {
+        return Err(tokens.err().unwrap());
+    }
+
+    let mut created = stored.token();
+    created.secret = Some(secret);
+
+    let mut tokens = tokens.unwrap();
+    tokens.push(stored);
+    write(root_dir, &tokens).map(|_| created)
+}
+
+/// Revokes a token, requests bearing it are turned away from now on
+pub fn revoke(root_dir: &str, id: &str) -> Result<(), StupidDbError> {
+    let _guard = TOKENS_LOCK.lock().unwrap();
+    let tokens = read(root_dir);
+    if tokens.is_err() {
+        return Err(tokens.err().unwrap());
+    }
+
+    let mut tokens = tokens.unwrap();
+    let length = tokens.len();
+    tokens.retain(|t| t.id != id);
+    if tokens.len() == length {
+        return Err(StupidDbError::TokenNotFound);
+    }
+
+    write(root_dir, &tokens)
+}
+
+fn read(root_dir: &str) -> Result<Vec<StoredToken>, StupidDbError> {
+    let path = file_path(root_dir);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|c| serde_json::from_str(c.as_str()).ok())
+        .ok_or(StupidDbError::TokenStoreFailed)
+}
+
+fn write(root_dir: &str, tokens: &Vec<StoredToken>) -> Result<(), StupidDbError> {
+    if fs::create_dir_all(root_dir).is_err() {
+        return Err(StupidDbError::TokenStoreFailed);
+    }
+
+    let path = file_path(root_dir);
+    let temporary = path.with_extension("tmp");
+    let content = serde_json::to_string(tokens).unwrap();
+    fs::write(&temporary, content)
+        .and_then(|_| fs::rename(&temporary, path))
+        .map_err(|_| StupidDbError::TokenStoreFailed)
+}
+
+fn file_path(root_dir: &str) -> PathBuf {
+    Path::new(root_dir).join(TOKENS_FILE)
+}
+
+fn hash(secret: &str) -> String {
+    hex(digest::digest(&digest::SHA256, secret.as_bytes()).as_ref())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(roles: &[(&str, StupidRole)]) -> StupidToken {
+        StupidToken {
+            id: String::new(),
+            name: String::from("test"),
+            roles: roles
+                .iter()
+                .map(|(key, role)| (String::from(*key), *role))
+                .collect(),
+            created: 0,
+            secret: None,
+        }
+    }
+
+    #[test]
+    fn matches_names_and_prefixes() {
+        assert!(matches("payments", "payments"));
+        assert!(!matches("payments", "payments.eu"));
+        assert!(matches("payments.*", "payments.eu"));
+        assert!(matches("payments.*", "payments."));
+        assert!(!matches("payments.*", "payments"));
+        assert!(!matches("payments.*", "invoices.eu"));
+        assert!(matches("*", "anything"));
+    }
+
+    #[test]
+    fn repository_role_covers_every_document() {
+        let token = token(&[("config.git", StupidRole::Write)]);
+        assert!(token.allows_document("config.git", "any", StupidRole::Read));
+        assert!(token.allows_document("config.git", "any", StupidRole::Write));
+        assert!(!token.allows_document("config.git", "any", StupidRole::Admin));
+        assert!(!token.allows_document("other.git", "any", StupidRole::Read));
+    }
+
+    #[test]
+    fn document_role_is_limited_to_its_prefix() {
+        let token = token(&[
+            ("config.git/payments.*", StupidRole::Write),
+            ("config.git/settings", StupidRole::Read),
+        ]);
+        assert!(token.allows_document("config.git", "payments.eu", StupidRole::Write));
+        assert!(!token.allows_document("config.git", "invoices", StupidRole::Read));
+        assert!(token.allows_document("config.git", "settings", StupidRole::Read));
+        assert!(!token.allows_document("config.git", "settings", StupidRole::Write));
+        assert!(!token.allows_document("config.git", "settings.old", StupidRole::Read));
+        assert!(!token.allows_document("other.git", "payments.eu", StupidRole::Read));
+        assert!(!token.allows("config.git", StupidRole::Read));
+    }
+
+    #[test]
+    fn document_role_on_every_repository() {
+        let token = token(&[("*/public.*", StupidRole::Read)]);
+        assert!(token.allows_document("a.git", "public.readme", StupidRole::Read));
+        assert!(token.allows_document("b.git", "public.readme", StupidRole::Read));
+        assert!(!token.allows_document("a.git", "private", StupidRole::Read));
+    }
+
+    #[test]
+    fn allows_some_with_a_document_role() {
+        let token = token(&[("config.git/payments.*", StupidRole::Write)]);
+        assert!(token.allows_some("config.git", StupidRole::Read));
+        assert!(token.allows_some("config.git", StupidRole::Write));
+        assert!(!token.allows_some("config.git", StupidRole::Admin));
+        assert!(!token.allows_some("other.git", StupidRole::Read));
+    }
+}
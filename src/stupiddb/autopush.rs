@@ -0,0 +1,48 @@
+use super::cache::SharedCache;
+use super::config::SharedConfig;
+use super::events;
+use super::models::StupidPushRequest;
+use super::StupidDb;
+use std::thread;
+
+/// Starts pushing repositories after every commit made to them, when the
+/// push settings ask for it
+pub fn start(config: &SharedConfig, cache: &SharedCache) {
+    let settings = config.read().unwrap().push.clone();
+    if !settings.on_commit {
+        return;
+    }
+
+    let config = config.clone();
+    let cache = cache.clone();
+    let receiver = events::subscribe();
+    thread::spawn(move || {
+        for event in receiver.iter() {
+            let mut db = StupidDb::new(&config, &cache);
+            let repository = db.open(&event.repository);
+            if repository.is_err() {
+                continue;
+            }
+
+            // Mirrors follow their remote, there is nothing to push back
+            let repo = repository.unwrap();
+            if repo.mirror.is_some() || (!settings.all_remotes && repo.origin.is_none()) {
+                continue;
+            }
+
+            let request = StupidPushRequest {
+                all_remotes: settings.all_remotes,
+                tags: settings.tags,
+                ..StupidPushRequest::default()
+            };
+            let result = repo.push(&request);
+            if result.is_err() {
+                eprintln!(
+                    "Repository {} failed to push: {:?}",
+                    event.repository,
+                    result.as_ref().err()
+                );
+            }
+        }
+    });
+}
@@ -0,0 +1,84 @@
+//! Opened repositories kept for later requests.
+//!
+//! A git repository handle is used by one request at a time, so a request
+//! takes the handle out of the cache and hands it back when it is done. A
+//! repository in use by two requests at once is opened a second time.
+
+use super::repository::StupidRepository;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// The cache of the running server, shared by all requests
+pub type SharedCache = Arc<RepositoryCache>;
+
+pub struct RepositoryCache {
+    /// Handles kept at most, the least recently used ones are closed first
+    capacity: usize,
+    entries: Mutex<Entries>,
+}
+
+#[derive(Default)]
+struct Entries {
+    /// Idle handles by repository name, with the tick they were last used at
+    handles: HashMap<String, (u64, StupidRepository)>,
+    /// Bumped whenever a repository is invalidated, handles taken out
+    /// before are not taken back
+    generations: HashMap<String, u64>,
+    tick: u64,
+}
+
+impl RepositoryCache {
+    pub fn new(capacity: usize) -> RepositoryCache {
+        RepositoryCache {
+            capacity,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    /// Takes the idle handle of a repository, along with the generation to
+    /// give back with it
+    pub fn take(&self, name: &str) -> (Option<StupidRepository>, u64) {
+        let mut entries = self.entries.lock().unwrap();
+        let generation = entries.generations.get(name).cloned().unwrap_or(0);
+        let handle = entries.handles.remove(name).map(|(_, repo)| repo);
+        (handle, generation)
+    }
+
+    /// Keeps a handle for the next request, unless its repository changed
+    /// since it was taken
+    pub fn give_back(&self, repo: StupidRepository, generation: u64) {
+        let name = repo.name();
+        if repo.is_stale() {
+            // Other handles of the repository miss the change as well
+            self.invalidate(name.as_str());
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        let current = entries.generations.get(&name).cloned().unwrap_or(0);
+        if generation != current || self.capacity == 0 {
+            return;
+        }
+
+        entries.tick += 1;
+        let tick = entries.tick;
+        entries.handles.insert(name, (tick, repo));
+        if entries.handles.len() > self.capacity {
+            let oldest = entries
+                .handles
+                .iter()
+                .min_by_key(|(_, (tick, _))| *tick)
+                .map(|(name, _)| name.to_string());
+            if let Some(oldest) = oldest {
+                entries.handles.remove(&oldest);
+            }
+        }
+    }
+
+    /// Closes the idle handle of a repository and turns away the ones in use
+    pub fn invalidate(&self, name: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.handles.remove(name);
+        *entries.generations.entry(String::from(name)).or_default() += 1;
+    }
+}
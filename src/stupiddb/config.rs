@@ -0,0 +1,414 @@
+//! Server configuration.
+//!
+//! Settings come from, by increasing precedence, their defaults, the
+//! configuration file, `STUPIDDB_*` environment variables and the command
+//! line. They are read once at startup and shared from then on.
+
+use super::encryption;
+use super::error::StupidDbError;
+use super::models::{
+    StupidConfig, StupidCredentials, StupidIdentity, StupidLogLevel, StupidSigningFormat,
+};
+use directories::ProjectDirs;
+use serde::de::DeserializeOwned;
+use std::env;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use validator::Validate;
+
+const APP_NAME: &str = "stupiddb";
+/// Fields of the configuration validated on their own
+const SECTIONS: [&str; 3] = ["server", "limits", "committer"];
+
+/// The configuration of the running server, shared by all requests
+pub type SharedConfig = Arc<RwLock<StupidConfig>>;
+
+/// Settings given on the command line
+#[derive(Debug, Default)]
+pub struct ConfigOverrides {
+    pub file: Option<PathBuf>,
+    pub root_dir: Option<String>,
+    pub address: Option<String>,
+    pub port: Option<u16>,
+    pub workers: Option<u16>,
+    pub log_level: Option<StupidLogLevel>,
+}
+
+/// A setting that could not be read or is invalid, naming where it came from
+#[derive(Debug)]
+pub struct ConfigError {
+    pub source: String,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.source, self.message)
+    }
+}
+
+/// Reads the configuration from all sources and validates it
+pub fn load(overrides: &ConfigOverrides) -> Result<StupidConfig, ConfigError> {
+    let file = overrides
+        .file
+        .clone()
+        .or(env::var_os("STUPIDDB_CONFIG").map(PathBuf::from));
+    let config = read_file(file.as_ref().map(|f| f.as_path()));
+    if config.is_err() {
+        return config;
+    }
+
+    let mut config = config.unwrap();
+    config.file = file;
+
+    let env_result = apply_env(&mut config);
+    if env_result.is_err() {
+        return Err(env_result.err().unwrap());
+    }
+
+    if let Some(root_dir) = overrides.root_dir.as_ref() {
+        config.root_dir = root_dir.to_string();
+    }
+    if let Some(address) = overrides.address.as_ref() {
+        config.server.address = address.to_string();
+    }
+    if let Some(port) = overrides.port {
+        config.server.port = port;
+    }
+    if overrides.workers.is_some() {
+        config.server.workers = overrides.workers;
+    }
+    if let Some(log_level) = overrides.log_level {
+        config.server.log_level = log_level;
+    }
+
+    validate(&config).map(|_| config)
+}
+
+/// Stores the credentials of a repository in the configuration file, and
+/// forgets them with None.
+///
+/// Only the credentials are written, settings from the environment or the
+/// command line stay out of the file. Tokens and passphrases are stored in
+/// clear, so the file is made readable by its owner only.
+pub fn store_credentials(
+    config: &SharedConfig,
+    name: &str,
+    credentials: Option<&StupidCredentials>,
+) -> Result<(), StupidDbError> {
+    let mut shared = config.write().unwrap();
+    let file = read_file(shared.file.as_ref().map(|f| f.as_path()));
+    if file.is_err() {
+        return Err(StupidDbError::CredentialsStoreFailed);
+    }
+
+    let mut file = file.unwrap();
+    match credentials {
+        Some(c) => file.credentials.insert(name.to_string(), c.clone()),
+        None => file.credentials.remove(name),
+    };
+
+    let path = file_path(shared.file.as_ref().map(|f| f.as_path()));
+    if path.is_none() {
+        return Err(StupidDbError::CredentialsStoreFailed);
+    }
+
+    let path = path.unwrap();
+    let store_result = restrict(path.as_path())
+        .map_err(|_| StupidDbError::CredentialsStoreFailed)
+        .and_then(|_| {
+            confy::store_path(&path, &file).map_err(|_| StupidDbError::CredentialsStoreFailed)
+        });
+    if store_result.is_err() {
+        return Err(store_result.err().unwrap());
+    }
+
+    shared.credentials = file.credentials;
+    Ok(())
+}
+
+/// The configuration file given, or the one confy keeps for the user
+fn file_path(file: Option<&Path>) -> Option<PathBuf> {
+    match file {
+        Some(path) => Some(path.to_path_buf()),
+        None => ProjectDirs::from("rs", "", APP_NAME)
+            .map(|p| p.config_dir().join(format!("{}.toml", APP_NAME))),
+    }
+}
+
+/// Creates a file only its owner may read and write, or takes the rights
+/// of others away from an existing one
+fn restrict(path: &Path) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .mode(0o600)
+        .open(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+}
+
+fn read_file(file: Option<&Path>) -> Result<StupidConfig, ConfigError> {
+    let result: Result<StupidConfig, confy::ConfyError> = match file {
+        Some(path) => confy::load_path(path),
+        None => confy::load(APP_NAME),
+    };
+
+    result.map_err(|e| ConfigError {
+        source: file.map_or(String::from("configuration file"), |f| {
+            f.display().to_string()
+        }),
+        message: e.to_string(),
+    })
+}
+
+fn apply_env(config: &mut StupidConfig) -> Result<(), ConfigError> {
+    if let Some(root_dir) = variable("STUPIDDB_ROOT_DIR") {
+        config.root_dir = root_dir;
+    }
+    if let Some(address) = variable("STUPIDDB_ADDRESS") {
+        config.server.address = address;
+    }
+    match parsed("STUPIDDB_PORT", "a port number") {
+        Some(Err(e)) => return Err(e),
+        Some(Ok(port)) => config.server.port = port,
+        None => {}
+    }
+    match parsed("STUPIDDB_WORKERS", "a number of threads") {
+        Some(Err(e)) => return Err(e),
+        Some(Ok(workers)) => config.server.workers = Some(workers),
+        None => {}
+    }
+    if let Some(log_level) = variable("STUPIDDB_LOG_LEVEL") {
+        let parsed_level = parse_log_level(log_level.as_str());
+        if parsed_level.is_err() {
+            return Err(ConfigError {
+                source: String::from("STUPIDDB_LOG_LEVEL"),
+                message: parsed_level.err().unwrap(),
+            });
+        }
+
+        config.server.log_level = parsed_level.unwrap();
+    }
+    match parsed("STUPIDDB_DOCUMENT_SIZE", "a number of bytes") {
+        Some(Err(e)) => return Err(e),
+        Some(Ok(size)) => config.limits.document_size = size,
+        None => {}
+    }
+    match parsed("STUPIDDB_WATCH_TIMEOUT", "a number of seconds") {
+        Some(Err(e)) => return Err(e),
+        Some(Ok(timeout)) => config.limits.watch_timeout = timeout,
+        None => {}
+    }
+    match parsed("STUPIDDB_WATCHERS", "a number of watches") {
+        Some(Err(e)) => return Err(e),
+        Some(Ok(watchers)) => config.limits.watchers = watchers,
+        None => {}
+    }
+    match parsed("STUPIDDB_CHANGE_FEEDS", "a number of feeds") {
+        Some(Err(e)) => return Err(e),
+        Some(Ok(feeds)) => config.limits.change_feeds = feeds,
+        None => {}
+    }
+    match parsed("STUPIDDB_OPEN_REPOSITORIES", "a number of repositories") {
+        Some(Err(e)) => return Err(e),
+        Some(Ok(open)) => config.limits.open_repositories = open,
+        None => {}
+    }
+
+    // Either part of the identity overrides the one of the file
+    let name = variable("STUPIDDB_COMMITTER_NAME");
+    let email = variable("STUPIDDB_COMMITTER_EMAIL");
+    if name.is_some() || email.is_some() {
+        let committer = config.committer.as_ref();
+        config.committer = Some(StupidIdentity {
+            name: name
+                .or(committer.map(|c| c.name.to_string()))
+                .unwrap_or_default(),
+            email: email
+                .or(committer.map(|c| c.email.to_string()))
+                .unwrap_or_default(),
+        });
+    }
+
+    match parsed("STUPIDDB_PUSH_ON_COMMIT", "true or false") {
+        Some(Err(e)) => return Err(e),
+        Some(Ok(on_commit)) => config.push.on_commit = on_commit,
+        None => {}
+    }
+    match parsed("STUPIDDB_PUSH_ALL_REMOTES", "true or false") {
+        Some(Err(e)) => return Err(e),
+        Some(Ok(all_remotes)) => config.push.all_remotes = all_remotes,
+        None => {}
+    }
+    match parsed("STUPIDDB_PUSH_TAGS", "true or false") {
+        Some(Err(e)) => return Err(e),
+        Some(Ok(tags)) => config.push.tags = tags,
+        None => {}
+    }
+    match parsed("STUPIDDB_AUTH_ENABLED", "true or false") {
+        Some(Err(e)) => return Err(e),
+        Some(Ok(enabled)) => config.auth.enabled = enabled,
+        None => {}
+    }
+
+    if let Some(key) = variable("STUPIDDB_SIGNING_KEY") {
+        config.signing.key = Some(key);
+    }
+    if let Some(format) = variable("STUPIDDB_SIGNING_FORMAT") {
+        let parsed_format = parse_name(format.as_str());
+        if parsed_format.is_none() {
+            return Err(ConfigError {
+                source: String::from("STUPIDDB_SIGNING_FORMAT"),
+                message: format!("unknown format \"{}\", expected ssh or gpg", format),
+            });
+        }
+
+        config.signing.format = parsed_format.unwrap();
+    }
+    if let Some(program) = variable("STUPIDDB_SIGNING_PROGRAM") {
+        config.signing.program = Some(program);
+    }
+    if let Some(allowed_signers) = variable("STUPIDDB_ALLOWED_SIGNERS") {
+        config.signing.allowed_signers = Some(allowed_signers);
+    }
+
+    // Keys are given as id:key pairs separated by commas
+    if let Some(keys) = variable("STUPIDDB_ENCRYPTION_KEYS") {
+        for pair in keys.split(',') {
+            let mut parts = pair.trim().splitn(2, ':');
+            match (parts.next(), parts.next()) {
+                (Some(id), Some(key)) if !id.is_empty() => {
+                    config
+                        .encryption
+                        .keys
+                        .insert(String::from(id), String::from(key));
+                }
+                _ => {
+                    return Err(ConfigError {
+                        source: String::from("STUPIDDB_ENCRYPTION_KEYS"),
+                        message: format!("invalid key \"{}\", expected <id>:<key>", pair),
+                    })
+                }
+            }
+        }
+    }
+    if let Some(key) = variable("STUPIDDB_ENCRYPTION_KEY") {
+        config.encryption.key = Some(key);
+    }
+
+    Ok(())
+}
+
+/// Parses log levels the way they are written in the configuration file
+pub fn parse_log_level(level: &str) -> Result<StupidLogLevel, String> {
+    parse_name(level).ok_or(format!(
+        "unknown log level \"{}\", expected critical, normal, debug or off",
+        level
+    ))
+}
+
+fn parse_name<T: DeserializeOwned>(name: &str) -> Option<T> {
+    serde_json::from_value(serde_json::Value::String(name.to_lowercase())).ok()
+}
+
+fn variable(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+fn parsed<T: FromStr>(name: &str, expected: &str) -> Option<Result<T, ConfigError>> {
+    variable(name).map(|value| {
+        value.parse::<T>().map_err(|_| ConfigError {
+            source: String::from(name),
+            message: format!("invalid value \"{}\", expected {}", value, expected),
+        })
+    })
+}
+
+fn validate(config: &StupidConfig) -> Result<(), ConfigError> {
+    let sections: Vec<(&str, Result<(), validator::ValidationErrors>)> = vec![
+        ("", config.validate()),
+        ("server.", config.server.validate()),
+        ("limits.", config.limits.validate()),
+        (
+            "committer.",
+            config.committer.as_ref().map_or(Ok(()), |c| c.validate()),
+        ),
+    ];
+
+    let mut fields: Vec<String> = Vec::new();
+    for (prefix, result) in sections {
+        if let Err(errors) = result {
+            fields.extend(
+                errors
+                    .field_errors()
+                    .keys()
+                    // Sections are reported with the fields that failed in them
+                    .filter(|f| !prefix.is_empty() || !SECTIONS.contains(f))
+                    .map(|f| format!("{}{}", prefix, f)),
+            );
+        }
+    }
+
+    if !fields.is_empty() {
+        fields.sort();
+        return Err(ConfigError {
+            source: String::from("configuration"),
+            message: format!("invalid value for {}", fields.join(", ")),
+        });
+    }
+
+    let root_dir = Path::new(config.root_dir.as_str());
+    if root_dir.exists() && !root_dir.is_dir() {
+        return Err(ConfigError {
+            source: String::from("root_dir"),
+            message: format!("{} is not a directory", config.root_dir),
+        });
+    }
+
+    // Ssh keys are files, a missing one would only fail the first commit
+    let ssh_key = config
+        .signing
+        .key
+        .as_ref()
+        .filter(|_| config.signing.format == StupidSigningFormat::Ssh);
+    if ssh_key.is_some() && !Path::new(ssh_key.unwrap().as_str()).is_file() {
+        return Err(ConfigError {
+            source: String::from("signing.key"),
+            message: format!("{} is not a file", ssh_key.unwrap()),
+        });
+    }
+
+    let mut key_ids: Vec<&String> = config.encryption.keys.keys().collect();
+    key_ids.sort();
+    let invalid_key = key_ids
+        .into_iter()
+        .find(|id| encryption::key_bytes(&config.encryption, id.as_str()).is_none());
+    if invalid_key.is_some() {
+        return Err(ConfigError {
+            source: format!("encryption.keys.{}", invalid_key.unwrap()),
+            message: format!(
+                "expected {} bytes encoded in base64",
+                encryption::KEY_LENGTH
+            ),
+        });
+    }
+
+    let active_key = config.encryption.key.as_ref();
+    if active_key.is_some() && !config.encryption.keys.contains_key(active_key.unwrap()) {
+        return Err(ConfigError {
+            source: String::from("encryption.key"),
+            message: format!("no key with id {}", active_key.unwrap()),
+        });
+    }
+
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use super::models::StupidCredentials;
+use std::cell::Cell;
+use std::path::Path;
+
+static DEFAULT_GIT_SSH_USERNAME: &str = "git";
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Builds remote callbacks that answer git2 credential requests
+/// from the repository's configured credentials.
+///
+/// Without configured credentials the ssh-agent is asked, which was
+/// the behaviour before credentials could be configured.
+pub fn callbacks<'a>(
+    credentials: Option<&'a StupidCredentials>,
+    config: Option<git2::Config>,
+) -> git2::RemoteCallbacks<'a> {
+    let attempts = Cell::new(0);
+    let mut callbacks = git2::RemoteCallbacks::new();
+    callbacks.credentials(move |url, user_from_url, cred_type| {
+        // git2 keeps asking as long as we hand out credentials,
+        // so a rejected key would otherwise loop forever.
+        attempts.set(attempts.get() + 1);
+        if attempts.get() > MAX_ATTEMPTS {
+            return Err(git2::Error::from_str("authentication failed"));
+        }
+
+        let user = username(credentials, user_from_url);
+        if cred_type.contains(git2::CredentialType::USERNAME) {
+            return git2::Cred::username(user.as_str());
+        }
+
+        match credentials {
+            None | Some(StupidCredentials::SshAgent { .. }) => {
+                git2::Cred::ssh_key_from_agent(user.as_str())
+            }
+            Some(StupidCredentials::SshKey {
+                private_key,
+                public_key,
+                passphrase,
+                ..
+            }) => git2::Cred::ssh_key(
+                user.as_str(),
+                public_key.as_ref().map(|p| Path::new(p.as_str())),
+                Path::new(private_key.as_str()),
+                passphrase.as_ref().map(|p| p.as_str()),
+            ),
+            Some(StupidCredentials::UserPass { username, password }) => {
+                git2::Cred::userpass_plaintext(username.as_str(), password.as_str())
+            }
+            Some(StupidCredentials::Helper { username }) => match config.as_ref() {
+                None => Err(git2::Error::from_str("no git config for credential helper")),
+                Some(c) => git2::Cred::credential_helper(
+                    c,
+                    url,
+                    username.as_ref().map(|u| u.as_str()).or(user_from_url),
+                ),
+            },
+        }
+    });
+
+    callbacks
+}
+
+fn username(credentials: Option<&StupidCredentials>, user_from_url: Option<&str>) -> String {
+    let configured = match credentials {
+        Some(StupidCredentials::SshAgent { username }) => username.as_ref(),
+        Some(StupidCredentials::SshKey { username, .. }) => username.as_ref(),
+        Some(StupidCredentials::UserPass { username, .. }) => Some(username),
+        Some(StupidCredentials::Helper { username }) => username.as_ref(),
+        None => None,
+    };
+
+    match (configured, user_from_url) {
+        (Some(u), _) => u.to_string(),
+        (None, Some(u)) => String::from(u),
+        (None, None) => String::from(DEFAULT_GIT_SSH_USERNAME),
+    }
+}
@@ -0,0 +1,383 @@
+//! Encryption at rest of documents, or of some of their fields.
+//!
+//! A repository declares which documents are encrypted in rules kept with
+//! its other data. Encrypted values are stored as an envelope naming the
+//! key they were sealed with, so documents stay JSON, fields left in clear
+//! stay diffable, and commits made before a key rotation stay readable as
+//! long as their key is configured.
+
+use super::auth;
+use super::error::StupidDbError;
+use super::models::{
+    StupidEncryptionConfig, StupidEncryptionRule, StupidEncryptionSettings, StupidEntry,
+    StupidRotationResult,
+};
+use super::query;
+use super::repository::StupidRepository;
+use ring::aead;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+
+const RULES_FILE: &str = "encryption.json";
+/// Only member of the object a sealed value is stored as
+const ENVELOPE: &str = "stupiddb:sealed";
+pub const KEY_LENGTH: usize = 32;
+const NONCE_LENGTH: usize = 12;
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    /// Id of the key, also authenticated along with the data
+    key: String,
+    nonce: String,
+    data: String,
+}
+
+/// A key to seal with
+struct Key {
+    id: String,
+    bytes: Vec<u8>,
+}
+
+/// The encryption rules of a repository
+pub fn config(repo: &StupidRepository) -> Result<StupidEncryptionConfig, StupidDbError> {
+    let dir = repo.data_dir();
+    if dir.is_err() {
+        return Err(dir.err().unwrap());
+    }
+
+    let path = dir.unwrap().join(RULES_FILE);
+    if !path.exists() {
+        return Ok(StupidEncryptionConfig::default());
+    }
+
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|c| serde_json::from_str(c.as_str()).ok())
+        .ok_or(StupidDbError::EncryptionStoreFailed)
+}
+
+/// Declares the encryption rules of a repository. Documents written from
+/// now on follow them, the ones already stored only after a rotation.
+pub fn set_config(
+    repo: &StupidRepository,
+    config: &StupidEncryptionConfig,
+) -> Result<StupidEncryptionConfig, StupidDbError> {
+    if !config.rules.is_empty() {
+        let key = active_key(&repo.encryption);
+        if key.is_err() {
+            return Err(key.err().unwrap());
+        }
+    }
+
+    let dir = repo.data_dir();
+    if dir.is_err() {
+        return Err(dir.err().unwrap());
+    }
+
+    let dir = dir.unwrap();
+    let temporary = dir.join(format!("{}.tmp", RULES_FILE));
+    let content = serde_json::to_string(config).unwrap();
+    let stored = fs::write(&temporary, content)
+        .and_then(|_| fs::rename(&temporary, dir.join(RULES_FILE)))
+        .map_err(|_| StupidDbError::EncryptionStoreFailed);
+    if stored.is_err() {
+        return Err(stored.err().unwrap());
+    }
+
+    Ok(config.clone())
+}
+
+/// A document as it is to be stored, with the values the rules of the
+/// repository cover sealed
+pub fn seal(repo: &StupidRepository, name: &str, content: &Value) -> Result<Value, StupidDbError> {
+    let config = config(repo);
+    if config.is_err() {
+        return Err(config.err().unwrap());
+    }
+
+    let config = config.unwrap();
+    let rules = covering(&config.rules, name);
+    if rules.is_empty() {
+        return Ok(content.clone());
+    }
+
+    let key = active_key(&repo.encryption);
+    if key.is_err() {
+        return Err(key.err().unwrap());
+    }
+
+    seal_with(&rules, &key.unwrap(), content)
+}
+
+/// A document as it was written, with every sealed value opened
+pub fn unseal(settings: &StupidEncryptionSettings, stored: Value) -> Result<Value, StupidDbError> {
+    match stored {
+        Value::Object(members) => match envelope(&members) {
+            Some(envelope) => open(settings, &envelope).and_then(|v| unseal(settings, v)),
+            None => members
+                .into_iter()
+                .map(|(k, v)| unseal(settings, v).map(|v| (k, v)))
+                .collect::<Result<serde_json::Map<String, Value>, StupidDbError>>()
+                .map(Value::Object),
+        },
+        Value::Array(values) => values
+            .into_iter()
+            .map(|v| unseal(settings, v))
+            .collect::<Result<Vec<Value>, StupidDbError>>()
+            .map(Value::Array),
+        value => Ok(value),
+    }
+}
+
+/// Stores the documents at HEAD again as a single commit, sealed with the
+/// current key and following the current rules
+pub fn rotate(
+    repo: &StupidRepository,
+    message: Option<&str>,
+) -> Result<StupidRotationResult, StupidDbError> {
+    let key = active_key(&repo.encryption);
+    if key.is_err() {
+        return Err(key.err().unwrap());
+    }
+
+    let config = config(repo);
+    if config.is_err() {
+        return Err(config.err().unwrap());
+    }
+
+    let key = key.unwrap();
+    let rules = config.unwrap().rules;
+    let mut result = StupidRotationResult {
+        commit: None,
+        key: key.id.to_string(),
+        documents: Vec::new(),
+    };
+
+    let head = repo.find_last_commit();
+    if head.is_none() {
+        return Ok(result);
+    }
+
+    let tree = head.unwrap().tree();
+    if tree.is_err() {
+        return Err(StupidDbError::TreeFetchFailed);
+    }
+
+    let index = repo.index();
+    if index.is_err() {
+        return Err(index.err().unwrap());
+    }
+
+    let mut index = index.unwrap();
+    for entry in tree.unwrap().iter() {
+        let name = entry.name().unwrap_or_default();
+        let stored = repo.read_stored_value(entry.id());
+        if stored.is_err() {
+            return Err(stored.err().unwrap());
+        }
+
+        let stored = stored.unwrap();
+        let sealed = unseal(&repo.encryption, stored.clone())
+            .and_then(|content| seal_with(&covering(&rules, name), &key, &content));
+        if sealed.is_err() {
+            return Err(sealed.err().unwrap());
+        }
+
+        // Documents in clear that stay in clear are left as they are
+        let sealed = sealed.unwrap();
+        if !is_sealed(&stored) && !is_sealed(&sealed) {
+            continue;
+        }
+
+        let oid = repo.blob(sealed.to_string().as_bytes());
+        if oid.is_err() {
+            return Err(oid.err().unwrap());
+        }
+
+        let entry = StupidEntry::new(oid.unwrap(), String::from(name).into_bytes());
+        if index.add(&entry.entry).is_err() {
+            return Err(StupidDbError::IndexFetchFailed);
+        }
+        result.documents.push(String::from(name));
+    }
+
+    if result.documents.is_empty() {
+        return Ok(result);
+    }
+
+    let tree_oid = index.write().and_then(|_| index.write_tree());
+    if tree_oid.is_err() {
+        return Err(StupidDbError::WriteTreeFailed);
+    }
+
+    let default_message = format!("Encrypt documents with key {}", key.id);
+    repo.commit_head(
+        tree_oid.unwrap(),
+        message.unwrap_or(default_message.as_str()),
+    )
+    .map(|oid| {
+        result.commit = Some(oid.to_string());
+        result
+    })
+}
+
+/// Rules applying to a document
+fn covering<'a>(rules: &'a Vec<StupidEncryptionRule>, name: &str) -> Vec<&'a StupidEncryptionRule> {
+    rules
+        .iter()
+        .filter(|r| auth::matches(r.documents.as_str(), name))
+        .collect()
+}
+
+fn seal_with(
+    rules: &Vec<&StupidEncryptionRule>,
+    key: &Key,
+    content: &Value,
+) -> Result<Value, StupidDbError> {
+    if rules.is_empty() {
+        return Ok(content.clone());
+    }
+
+    // A rule without fields covers the whole document
+    if rules.iter().any(|r| r.fields.is_empty()) {
+        return close(key, content);
+    }
+
+    // Inner fields first, so a field holding another one seals it along
+    let mut pointers: Vec<String> = rules
+        .iter()
+        .flat_map(|r| r.fields.iter())
+        .filter_map(|f| query::pointer(f.as_str()))
+        .collect();
+    pointers.sort_by(|a, b| b.len().cmp(&a.len()).then(a.cmp(b)));
+    pointers.dedup();
+
+    let mut sealed = content.clone();
+    for pointer in pointers {
+        if let Some(field) = sealed.pointer_mut(pointer.as_str()) {
+            let closed = close(key, field);
+            if closed.is_err() {
+                return Err(closed.err().unwrap());
+            }
+            *field = closed.unwrap();
+        }
+    }
+
+    Ok(sealed)
+}
+
+/// Seals a value into an envelope
+fn close(key: &Key, value: &Value) -> Result<Value, StupidDbError> {
+    let sealing_key = aead::SealingKey::new(&aead::AES_256_GCM, key.bytes.as_slice());
+    if sealing_key.is_err() {
+        return Err(StupidDbError::EncryptionFailed);
+    }
+
+    let mut nonce = [0u8; NONCE_LENGTH];
+    if SystemRandom::new().fill(&mut nonce).is_err() {
+        return Err(StupidDbError::EncryptionFailed);
+    }
+
+    let tag_length = aead::AES_256_GCM.tag_len();
+    let mut data = value.to_string().into_bytes();
+    data.extend(vec![0u8; tag_length]);
+    let sealed = aead::seal_in_place(
+        &sealing_key.unwrap(),
+        &nonce,
+        key.id.as_bytes(),
+        &mut data,
+        tag_length,
+    );
+    if sealed.is_err() {
+        return Err(StupidDbError::EncryptionFailed);
+    }
+
+    let envelope = Envelope {
+        key: key.id.to_string(),
+        nonce: base64::encode(&nonce),
+        data: base64::encode(&data[..sealed.unwrap()]),
+    };
+    Ok(serde_json::json!({ ENVELOPE: envelope }))
+}
+
+/// Opens an envelope with the key it names
+fn open(settings: &StupidEncryptionSettings, envelope: &Envelope) -> Result<Value, StupidDbError> {
+    let bytes = key_bytes(settings, envelope.key.as_str());
+    if bytes.is_none() {
+        return Err(StupidDbError::EncryptionKeyNotFound);
+    }
+
+    let opening_key = aead::OpeningKey::new(&aead::AES_256_GCM, bytes.unwrap().as_slice());
+    let nonce = base64::decode(envelope.nonce.as_str());
+    let data = base64::decode(envelope.data.as_str());
+    if opening_key.is_err() || nonce.is_err() || data.is_err() {
+        return Err(StupidDbError::DecryptionFailed);
+    }
+
+    let mut data = data.unwrap();
+    let opened = aead::open_in_place(
+        &opening_key.unwrap(),
+        nonce.unwrap().as_slice(),
+        envelope.key.as_bytes(),
+        0,
+        &mut data,
+    );
+    if opened.is_err() {
+        return Err(StupidDbError::DecryptionFailed);
+    }
+
+    serde_json::from_slice(opened.unwrap()).map_err(|_| StupidDbError::DecryptionFailed)
+}
+
+/// The envelope an object is, None when it is an ordinary object
+fn envelope(members: &serde_json::Map<String, Value>) -> Option<Envelope> {
+    if members.len() != 1 {
+        return None;
+    }
+
+    members
+        .get(ENVELOPE)
+        .and_then(|e| serde_json::from_value(e.clone()).ok())
+}
+
+/// Whether a value is itself sealed
+pub fn is_envelope(value: &Value) -> bool {
+    match value {
+        Value::Object(members) => envelope(members).is_some(),
+        _ => false,
+    }
+}
+
+/// Whether a value holds a sealed value anywhere
+pub fn is_sealed(value: &Value) -> bool {
+    match value {
+        Value::Object(members) => {
+            envelope(members).is_some() || members.values().any(|v| is_sealed(v))
+        }
+        Value::Array(values) => values.iter().any(|v| is_sealed(v)),
+        _ => false,
+    }
+}
+
+fn active_key(settings: &StupidEncryptionSettings) -> Result<Key, StupidDbError> {
+    let id = settings.key.as_ref();
+    let bytes = id.and_then(|id| key_bytes(settings, id.as_str()));
+    match (id, bytes) {
+        (Some(id), Some(bytes)) => Ok(Key {
+            id: id.to_string(),
+            bytes,
+        }),
+        _ => Err(StupidDbError::EncryptionKeyNotFound),
+    }
+}
+
+/// Decoded key of an id, None when it is unknown or invalid
+pub fn key_bytes(settings: &StupidEncryptionSettings, id: &str) -> Option<Vec<u8>> {
+    settings
+        .keys
+        .get(id)
+        .and_then(|k| base64::decode(k.as_str()).ok())
+        .filter(|k| k.len() == KEY_LENGTH)
+}
@@ -15,4 +15,42 @@ pub enum StupidDbError {
     RemoteConnectFailed,
     RemotePushFailed,
     RemoteUrlFailed,
+    RemoteNotFound,
+    RemoteExists,
+    RemoteDeleteFailed,
+    CredentialsStoreFailed,
+    TagFetchFailed,
+    TagExists,
+    TagFailed,
+    ConfigFailed,
+    RemoteFetchFailed,
+    ResetFailed,
+    RepoIsMirror,
+    RepoExists,
+    RepoDeleteFailed,
+    RepoRenameFailed,
+    ConfirmationRequired,
+    DocumentNotFound,
+    RevisionNotFound,
+    DiffFailed,
+    WebhookNotFound,
+    WebhookStoreFailed,
+    TimeoutInvalid,
+    TooManyWatchers,
+    IndexStoreFailed,
+    PathInvalid,
+    ContentInvalid,
+    BranchNotFound,
+    BranchExists,
+    BranchIsHead,
+    BranchFailed,
+    Unauthorized,
+    Forbidden,
+    TokenNotFound,
+    TokenStoreFailed,
+    SigningFailed,
+    EncryptionKeyNotFound,
+    EncryptionFailed,
+    DecryptionFailed,
+    EncryptionStoreFailed,
 }
@@ -0,0 +1,78 @@
+use super::models::StupidChangeEvent;
+use lazy_static::lazy_static;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Mutex;
+use std::time::Instant;
+
+lazy_static! {
+    static ref SUBSCRIBERS: Mutex<Vec<Sender<StupidChangeEvent>>> = Mutex::new(Vec::new());
+}
+
+/// Receives every change event published from now on
+pub fn subscribe() -> Receiver<StupidChangeEvent> {
+    let (sender, receiver) = channel();
+    SUBSCRIBERS.lock().unwrap().push(sender);
+    receiver
+}
+
+/// Hands an event to all subscribers, forgetting those that went away
+pub fn publish(event: StupidChangeEvent) {
+    SUBSCRIBERS
+        .lock()
+        .unwrap()
+        .retain(|s| s.send(event.clone()).is_ok());
+}
+
+/// One of a limited number of places for requests that block on events,
+/// given back when dropped
+#[derive(Debug)]
+pub struct Slot(&'static AtomicUsize);
+
+impl Slot {
+    /// Takes one of the `limit` places counted by `taken`, None when they
+    /// are all taken
+    pub fn take(taken: &'static AtomicUsize, limit: usize) -> Option<Slot> {
+        if taken.fetch_add(1, Ordering::SeqCst) >= limit {
+            taken.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+
+        Some(Slot(taken))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Blocks until an event of the receiver changes the document on the given
+/// ref, false when the deadline passes first
+pub fn wait_for_change(
+    receiver: &Receiver<StupidChangeEvent>,
+    repository: &str,
+    reference: &str,
+    name: &str,
+    deadline: Instant,
+) -> bool {
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return false;
+        }
+
+        match receiver.recv_timeout(deadline - now) {
+            Ok(event) => {
+                if event.repository == repository
+                    && event.reference.as_ref().map(|r| r.as_str()) == Some(reference)
+                    && event.changes.iter().any(|c| c.name == name)
+                {
+                    return true;
+                }
+            }
+            Err(_) => return false,
+        }
+    }
+}
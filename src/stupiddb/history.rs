@@ -0,0 +1,391 @@
+use super::error::StupidDbError;
+use super::models::{
+    StupidChange, StupidChangeEvent, StupidChangeKind, StupidPickaxeHit, StupidPickaxeKind,
+    StupidPickaxeQuery, StupidSignatureStatus,
+};
+use super::query;
+use super::repository::StupidRepository;
+use super::signing;
+
+/// A document that differs between two trees
+pub struct DocumentDelta {
+    pub name: String,
+    /// Blob before, None when the document was added
+    pub old: Option<git2::Oid>,
+    /// Blob after, None when the document was deleted
+    pub new: Option<git2::Oid>,
+}
+
+impl StupidRepository {
+    /// Documents that differ between two trees, a missing tree stands for an
+    /// empty one
+    pub fn diff_documents(
+        &self,
+        old: Option<&git2::Tree>,
+        new: Option<&git2::Tree>,
+    ) -> Result<Vec<DocumentDelta>, StupidDbError> {
+        let repo = self.repository();
+        if repo.is_err() {
+            return Err(repo.err().unwrap());
+        }
+
+        let diff = repo.unwrap().diff_tree_to_tree(old, new, None);
+        if diff.is_err() {
+            return Err(StupidDbError::DiffFailed);
+        }
+
+        let blob = |file: git2::DiffFile| match file.id().is_zero() {
+            true => None,
+            false => Some(file.id()),
+        };
+        Ok(diff
+            .unwrap()
+            .deltas()
+            .filter_map(|delta| {
+                let name = delta
+                    .new_file()
+                    .path()
+                    .or(delta.old_file().path())
+                    .and_then(|p| p.to_str())
+                    .map(String::from);
+
+                name.map(|name| DocumentDelta {
+                    name,
+                    old: blob(delta.old_file()),
+                    new: blob(delta.new_file()),
+                })
+            })
+            .collect())
+    }
+
+    /// Tree of the commit a revision points at
+    pub fn find_revision_tree(&self, revision: &str) -> Option<git2::Tree> {
+        self.find_revision_commit(revision)
+            .and_then(|c| c.tree().ok())
+    }
+
+    /// Names of the documents a commit changed compared to its first parent
+    pub fn commit_documents(&self, revision: &str) -> Result<Vec<String>, StupidDbError> {
+        let commit = self.find_revision_commit(revision);
+        if commit.is_none() {
+            return Err(StupidDbError::RevisionNotFound);
+        }
+
+        let commit = commit.unwrap();
+        let parent_tree = commit.parent(0).and_then(|p| p.tree()).ok();
+        self.diff_documents(parent_tree.as_ref(), commit.tree().ok().as_ref())
+            .map(|deltas| deltas.into_iter().map(|d| d.name).collect())
+    }
+
+    /// Tree of the commit with the given id
+    pub fn find_commit_tree(&self, id: &str) -> Option<git2::Tree> {
+        let repo = self.repository().ok()?;
+        let oid = git2::Oid::from_str(id).ok()?;
+        repo.find_commit(oid).and_then(|c| c.tree()).ok()
+    }
+
+    /// Describes the documents a commit changed relative to its first parent
+    pub fn change_event(
+        &self,
+        commit: &git2::Commit,
+        reference: Option<&str>,
+    ) -> Result<StupidChangeEvent, StupidDbError> {
+        let tree = commit.tree();
+        if tree.is_err() {
+            return Err(StupidDbError::TreeFetchFailed);
+        }
+
+        let parent_tree = commit.parent(0).and_then(|p| p.tree()).ok();
+        let deltas = self.diff_documents(parent_tree.as_ref(), tree.as_ref().ok());
+        if deltas.is_err() {
+            return Err(deltas.err().unwrap());
+        }
+
+        let changes = deltas
+            .unwrap()
+            .into_iter()
+            .map(|delta| StupidChange {
+                kind: match (delta.old, delta.new) {
+                    (None, _) => StupidChangeKind::Added,
+                    (_, None) => StupidChangeKind::Deleted,
+                    _ => StupidChangeKind::Modified,
+                },
+                id: delta.new.map(|oid| oid.to_string()),
+                name: delta.name,
+            })
+            .collect();
+
+        Ok(StupidChangeEvent {
+            repository: self.name(),
+            commit: commit.id().to_string(),
+            reference: reference.map(String::from),
+            message: commit.message().map(String::from),
+            time: commit.time().seconds(),
+            changes,
+            signature: None,
+        })
+    }
+
+    /// Checks the signature of a commit against the keys the server trusts
+    pub fn verify_commit(&self, id: git2::Oid) -> StupidSignatureStatus {
+        let repo = self.repository();
+        if repo.is_err() {
+            return StupidSignatureStatus::Unknown;
+        }
+
+        match repo.unwrap().extract_signature(&id, None) {
+            Ok((signature, data)) => {
+                signing::verify_commit(&self.signing, id.to_string().as_str(), &signature, &data)
+            }
+            Err(_) => StupidSignatureStatus::Unsigned,
+        }
+    }
+
+    /// Change events of the commits on a ref after the given commit, oldest
+    /// first, all commits on the ref when no commit is given
+    pub fn changes_since(
+        &self,
+        reference: &str,
+        since: Option<&str>,
+    ) -> Result<Vec<StupidChangeEvent>, StupidDbError> {
+        let repo = self.repository();
+        if repo.is_err() {
+            return Err(repo.err().unwrap());
+        }
+
+        let repo = repo.unwrap();
+        let head = repo.refname_to_id(reference);
+        if head.is_err() {
+            // A branch without commits has no changes yet
+            return Ok(Vec::new());
+        }
+
+        let commits = self.walk(
+            head.unwrap(),
+            since,
+            git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE,
+        );
+        if commits.is_err() {
+            return Err(commits.err().unwrap());
+        }
+
+        commits
+            .unwrap()
+            .into_iter()
+            .map(|oid| {
+                repo.find_commit(oid)
+                    .map_err(|_| StupidDbError::CommitFetchFailed)
+                    .and_then(|c| self.change_event(&c, Some(reference)))
+            })
+            .collect()
+    }
+
+    /// Commits reachable from HEAD or from the given revision, newest
+    /// first, with their signatures checked.
+    ///
+    /// Commits that only changed documents not `visible` to the caller are
+    /// left out, as are those changes.
+    pub fn log(
+        &self,
+        revision: Option<&str>,
+        limit: Option<usize>,
+        visible: &dyn Fn(&str) -> bool,
+    ) -> Result<Vec<StupidChangeEvent>, StupidDbError> {
+        let repo = self.repository();
+        if repo.is_err() {
+            return Err(repo.err().unwrap());
+        }
+
+        let head = match revision {
+            Some(r) => self.find_revision_commit(r),
+            None => self.find_last_commit(),
+        };
+        if head.is_none() {
+            return match revision {
+                Some(_) => Err(StupidDbError::RevisionNotFound),
+                None => Ok(Vec::new()),
+            };
+        }
+
+        let commits = self.walk(
+            head.unwrap().id(),
+            None,
+            git2::Sort::TOPOLOGICAL | git2::Sort::TIME,
+        );
+        if commits.is_err() {
+            return Err(commits.err().unwrap());
+        }
+
+        let repo = repo.unwrap();
+        let mut events = Vec::new();
+        for oid in commits.unwrap() {
+            if limit.map_or(false, |l| events.len() >= l) {
+                break;
+            }
+
+            let event = repo
+                .find_commit(oid)
+                .map_err(|_| StupidDbError::CommitFetchFailed)
+                .and_then(|c| self.change_event(&c, None));
+            if event.is_err() {
+                return Err(event.err().unwrap());
+            }
+
+            // Left out before counting, the limit is on the commits returned
+            let mut event = event.unwrap();
+            if !event.changes.is_empty() && !event.changes.iter().any(|c| visible(c.name.as_str()))
+            {
+                continue;
+            }
+
+            event.changes.retain(|c| visible(c.name.as_str()));
+            event.signature = Some(self.verify_commit(oid));
+            events.push(event);
+        }
+
+        Ok(events)
+    }
+
+    /// Commits in which the value at a path of a document changed, newest
+    /// first.
+    ///
+    /// With a value, only commits in which the path started or stopped
+    /// holding that value are returned. Merge commits are compared to
+    /// their first parent. Documents not `visible` to the caller are left
+    /// out.
+    pub fn pickaxe(
+        &self,
+        query: &StupidPickaxeQuery,
+        visible: &dyn Fn(&str) -> bool,
+    ) -> Result<Vec<StupidPickaxeHit>, StupidDbError> {
+        let repo = self.repository();
+        if repo.is_err() {
+            return Err(repo.err().unwrap());
+        }
+
+        let repo = repo.unwrap();
+        let pointer = query::pointer(query.path.as_str());
+        if pointer.is_none() {
+            return Err(StupidDbError::PathInvalid);
+        }
+
+        let head = match query.to.as_ref() {
+            Some(to) => self.find_revision_commit(to.as_str()),
+            None => self.find_last_commit(),
+        };
+        if head.is_none() {
+            return match query.to {
+                Some(_) => Err(StupidDbError::RevisionNotFound),
+                None => Ok(Vec::new()),
+            };
+        }
+
+        let commits = self.walk(
+            head.unwrap().id(),
+            query.from.as_ref().map(|f| f.as_str()),
+            git2::Sort::TOPOLOGICAL | git2::Sort::TIME,
+        );
+        if commits.is_err() {
+            return Err(commits.err().unwrap());
+        }
+
+        let pointer = pointer.unwrap();
+        let limit = query.limit.unwrap_or(usize::MAX);
+        let mut hits = Vec::new();
+        for oid in commits.unwrap() {
+            let commit = repo.find_commit(oid);
+            if commit.is_err() {
+                return Err(StupidDbError::CommitFetchFailed);
+            }
+
+            // Checked once for all hits of the commit, and only if it has any
+            let mut signature = None;
+            let commit = commit.unwrap();
+            let parent_tree = commit.parent(0).and_then(|p| p.tree()).ok();
+            let deltas = self.diff_documents(parent_tree.as_ref(), commit.tree().ok().as_ref());
+            if deltas.is_err() {
+                return Err(deltas.err().unwrap());
+            }
+
+            for delta in deltas.unwrap() {
+                if hits.len() >= limit {
+                    return Ok(hits);
+                }
+
+                let prefix = query.prefix.as_ref().map_or("", |p| p.as_str());
+                if !delta.name.starts_with(prefix) || !visible(delta.name.as_str()) {
+                    continue;
+                }
+
+                let value_at = |blob: Option<git2::Oid>| {
+                    blob.and_then(|oid| self.read_blob_value(oid).ok())
+                        .and_then(|content| content.pointer(pointer.as_str()).cloned())
+                };
+                let old = value_at(delta.old);
+                let new = value_at(delta.new);
+                let kind = match query.value.as_ref() {
+                    None if old == new => continue,
+                    None => StupidPickaxeKind::Changed,
+                    Some(v) => match (old.as_ref() == Some(v), new.as_ref() == Some(v)) {
+                        (false, true) => StupidPickaxeKind::Appeared,
+                        (true, false) => StupidPickaxeKind::Disappeared,
+                        _ => continue,
+                    },
+                };
+
+                hits.push(StupidPickaxeHit {
+                    commit: commit.id().to_string(),
+                    message: commit.message().map(String::from),
+                    time: commit.time().seconds(),
+                    document: delta.name,
+                    kind,
+                    old,
+                    new,
+                    signature: *signature.get_or_insert_with(|| self.verify_commit(oid)),
+                });
+            }
+        }
+
+        Ok(hits)
+    }
+
+    /// Commits reachable from a commit but not from the given revision
+    fn walk(
+        &self,
+        to: git2::Oid,
+        from: Option<&str>,
+        sorting: git2::Sort,
+    ) -> Result<Vec<git2::Oid>, StupidDbError> {
+        let repo = self.repository();
+        if repo.is_err() {
+            return Err(repo.err().unwrap());
+        }
+
+        let repo = repo.unwrap();
+        let revwalk = repo.revwalk();
+        if revwalk.is_err() {
+            return Err(StupidDbError::CommitFetchFailed);
+        }
+
+        let mut revwalk = revwalk.unwrap();
+        let walk_result = revwalk.set_sorting(sorting).and_then(|_| revwalk.push(to));
+        if walk_result.is_err() {
+            return Err(StupidDbError::CommitFetchFailed);
+        }
+
+        if from.is_some() {
+            let from_commit = repo
+                .revparse_single(from.unwrap())
+                .and_then(|o| o.peel_to_commit());
+            if from_commit.is_err() {
+                return Err(StupidDbError::RevisionNotFound);
+            }
+
+            if revwalk.hide(from_commit.unwrap().id()).is_err() {
+                return Err(StupidDbError::CommitFetchFailed);
+            }
+        }
+
+        Ok(revwalk.filter_map(|oid| oid.ok()).collect())
+    }
+}
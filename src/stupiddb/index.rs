@@ -0,0 +1,653 @@
+//! Secondary indexes from JSON field values to document names.
+//!
+//! Each branch has an index of its own, reflecting the tree of the commit
+//! the branch pointed at when it was last updated. Commits made through
+//! the repository move the index of their branch along by applying the
+//! diff between the old and the new tree, anything else leaves it stale
+//! until it is rebuilt from the commit of the branch.
+//!
+//! The manifest names the paths and, by ref, the commit each index
+//! reflects. Each path of an index is kept in a file of its own, so
+//! queries only load the paths they filter on and commits only rewrite the
+//! paths whose values they changed.
+//!
+//! Values are indexed as stored, so nothing encryption seals is ever
+//! written in clear. Documents whose value at a path is sealed are kept
+//! aside as candidates of every predicate on the path.
+
+use super::encryption;
+use super::error::StupidDbError;
+use super::models::{StupidIndexStatus, StupidPredicate};
+use super::query;
+use super::repository::StupidRepository;
+use lazy_static::lazy_static;
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+const INDEX_DIR: &str = "indexes";
+const MANIFEST_FILE: &str = "manifest.json";
+
+lazy_static! {
+    /// Commits on different threads update the same index files, queries
+    /// must not read them halfway through an update
+    static ref INDEX_LOCK: RwLock<()> = RwLock::new(());
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Manifest {
+    /// Declared paths, JSON Pointers or JSONPaths
+    paths: Vec<String>,
+    /// Commit the values were read from by indexed ref
+    #[serde(default)]
+    commits: BTreeMap<String, String>,
+    /// Error of the last update that failed by ref, until the index is
+    /// updated or rebuilt
+    #[serde(default)]
+    failures: BTreeMap<String, String>,
+}
+
+/// Values of the documents at a single path
+#[derive(Serialize, Deserialize, Default)]
+struct PathIndex {
+    /// Document names by serialized value
+    values: BTreeMap<String, BTreeSet<String>>,
+    /// Names of the documents whose value is sealed
+    #[serde(default)]
+    sealed: BTreeSet<String>,
+}
+
+impl PathIndex {
+    fn add(&mut self, path: &str, name: &str, content: &Value) {
+        match query::lookup(content, path) {
+            Some(value) if !encryption::is_sealed(value) => {
+                self.values
+                    .entry(key(value))
+                    .or_default()
+                    .insert(String::from(name));
+            }
+            // The path may lead into a sealed value as well as be missing
+            _ if encryption::is_sealed(content) => {
+                self.sealed.insert(String::from(name));
+            }
+            _ => {}
+        }
+    }
+
+    fn remove(&mut self, path: &str, name: &str, content: &Value) {
+        self.sealed.remove(name);
+
+        let value = query::lookup(content, path);
+        if value.is_none() {
+            return;
+        }
+
+        let value_key = key(value.unwrap());
+        let names = self.values.get_mut(&value_key);
+        if names.is_none() {
+            return;
+        }
+
+        let names = names.unwrap();
+        names.remove(name);
+        if names.is_empty() {
+            self.values.remove(&value_key);
+        }
+    }
+}
+
+/// Paths, commit and freshness of the index of a branch, of the one HEAD
+/// points at when none is given
+pub fn status(
+    repo: &StupidRepository,
+    reference: Option<&str>,
+) -> Result<StupidIndexStatus, StupidDbError> {
+    let manifest = load_manifest(repo);
+    if manifest.is_err() {
+        return Err(manifest.err().unwrap());
+    }
+
+    let manifest = manifest.unwrap();
+    let reference = reference.map(String::from).or(repo.head_branch());
+    let commit = reference
+        .as_ref()
+        .and_then(|r| manifest.commits.get(r))
+        .cloned();
+    let error = reference
+        .as_ref()
+        .and_then(|r| manifest.failures.get(r))
+        .cloned();
+    let current = reference
+        .as_ref()
+        .and_then(|r| repo.find_revision_commit(r.as_str()))
+        .map(|c| c.id().to_string());
+    Ok(StupidIndexStatus {
+        stale: !manifest.paths.is_empty() && (commit != current || error.is_some()),
+        paths: manifest.paths,
+        reference,
+        commit,
+        error,
+    })
+}
+
+/// Declares the indexed paths and builds the index of the branch HEAD
+/// points at for them, dropping the indexes of other branches
+pub fn set_paths(
+    repo: &StupidRepository,
+    paths: &Vec<String>,
+) -> Result<StupidIndexStatus, StupidDbError> {
+    let guard = INDEX_LOCK.write().unwrap();
+    let dir = index_dir(repo);
+    if dir.is_err() {
+        return Err(dir.err().unwrap());
+    }
+
+    if fs::remove_dir_all(dir.unwrap()).is_err() {
+        return Err(StupidDbError::IndexStoreFailed);
+    }
+
+    let mut manifest = Manifest {
+        paths: paths.clone(),
+        commits: BTreeMap::new(),
+        failures: BTreeMap::new(),
+    };
+    let head = repo.head_branch();
+    if let Some(head) = head.as_ref().filter(|_| !paths.is_empty()) {
+        let built = build(repo, &mut manifest, head.as_str());
+        if built.is_err() {
+            return Err(built.err().unwrap());
+        }
+    }
+
+    let saved = save_manifest(repo, &manifest);
+    if saved.is_err() {
+        return Err(saved.err().unwrap());
+    }
+
+    drop(guard);
+    status(repo, None)
+}
+
+/// Builds the index of a branch again from its commit, of the branch HEAD
+/// points at when none is given
+pub fn rebuild(
+    repo: &StupidRepository,
+    reference: Option<&str>,
+) -> Result<StupidIndexStatus, StupidDbError> {
+    let reference = reference.map(String::from).or(repo.head_branch());
+    if reference.is_none() {
+        return Err(StupidDbError::HeadFetchFailed);
+    }
+
+    let reference = reference.unwrap();
+    if repo.find_revision_commit(reference.as_str()).is_none() {
+        return Err(StupidDbError::BranchNotFound);
+    }
+
+    let guard = INDEX_LOCK.write().unwrap();
+    let manifest = load_manifest(repo);
+    if manifest.is_err() {
+        return Err(manifest.err().unwrap());
+    }
+
+    let mut manifest = manifest.unwrap();
+    if manifest.paths.is_empty() {
+        drop(guard);
+        return status(repo, Some(reference.as_str()));
+    }
+
+    let built =
+        build(repo, &mut manifest, reference.as_str()).and_then(|_| save_manifest(repo, &manifest));
+    if built.is_err() {
+        return Err(built.err().unwrap());
+    }
+
+    drop(guard);
+    status(repo, Some(reference.as_str()))
+}
+
+/// Moves the index of a branch to the commit the branch points at now.
+///
+/// A branch without an index yet starts from a copy of the index of
+/// another branch, so only the documents the branches differ in are read.
+/// A failure is recorded in the manifest for the status to report.
+pub fn update(repo: &StupidRepository, reference: &str) -> Result<(), StupidDbError> {
+    let _guard = INDEX_LOCK.write().unwrap();
+    let updated = apply(repo, reference);
+    if updated.is_err() {
+        let _ = load_manifest(repo).and_then(|mut manifest| {
+            manifest.failures.insert(
+                String::from(reference),
+                format!("{:?}", updated.as_ref().err().unwrap()),
+            );
+            save_manifest(repo, &manifest)
+        });
+    }
+
+    updated
+}
+
+/// Applies the commits of a branch since the commit its index reflects
+fn apply(repo: &StupidRepository, reference: &str) -> Result<(), StupidDbError> {
+    let manifest = load_manifest(repo);
+    if manifest.is_err() {
+        return Err(manifest.err().unwrap());
+    }
+
+    let mut manifest = manifest.unwrap();
+    let head = repo.find_revision_commit(reference);
+    if manifest.paths.is_empty() || head.is_none() {
+        return Ok(());
+    }
+
+    let head = head.unwrap();
+    let head_id = head.id().to_string();
+    if manifest.commits.get(reference) == Some(&head_id) {
+        return Ok(());
+    }
+
+    if !manifest.commits.contains_key(reference) {
+        let seeded = seed(repo, &mut manifest, reference);
+        if seeded.is_err() {
+            return Err(seeded.err().unwrap());
+        }
+    }
+
+    let old_tree = manifest
+        .commits
+        .get(reference)
+        .and_then(|c| repo.find_commit_tree(c.as_str()));
+    if old_tree.is_none() {
+        // Without the old tree there is nothing to diff against
+        return build(repo, &mut manifest, reference).and_then(|_| save_manifest(repo, &manifest));
+    }
+
+    let deltas = repo.diff_documents(old_tree.as_ref(), head.tree().ok().as_ref());
+    if deltas.is_err() {
+        return Err(deltas.err().unwrap());
+    }
+
+    // Documents are read once, then applied path by path
+    let mut changes = Vec::new();
+    for delta in deltas.unwrap() {
+        let old = delta.old.and_then(|old| repo.read_stored_value(old).ok());
+        let new = match delta.new {
+            Some(new) => repo.read_stored_value(new).map(Some),
+            None => Ok(None),
+        };
+        if new.is_err() {
+            return Err(new.err().unwrap());
+        }
+
+        changes.push((delta.name, old, new.unwrap()));
+    }
+
+    for path in manifest.paths.iter() {
+        let changed: Vec<&(String, Option<Value>, Option<Value>)> = changes
+            .iter()
+            .filter(|(_, old, new)| !unchanged(path, old.as_ref(), new.as_ref()))
+            .collect();
+        if changed.is_empty() {
+            continue;
+        }
+
+        let path_index = load_path(repo, reference, path);
+        if path_index.is_err() {
+            return Err(path_index.err().unwrap());
+        }
+
+        let mut path_index = path_index.unwrap();
+        for (name, old, new) in changed {
+            if let Some(old) = old {
+                path_index.remove(path, name, old);
+            }
+
+            if let Some(new) = new {
+                path_index.add(path, name, new);
+            }
+        }
+
+        let saved = save_path(repo, reference, path, &path_index);
+        if saved.is_err() {
+            return Err(saved.err().unwrap());
+        }
+    }
+
+    // Paths saved before a failure here are applied again by the next
+    // update, adding and removing names twice leaves them the same
+    manifest.commits.insert(String::from(reference), head_id);
+    manifest.failures.remove(reference);
+    save_manifest(repo, &manifest)
+}
+
+/// Drops the index of a branch that went away
+pub fn remove(repo: &StupidRepository, reference: &str) -> Result<(), StupidDbError> {
+    let _guard = INDEX_LOCK.write().unwrap();
+    let manifest = load_manifest(repo);
+    if manifest.is_err() {
+        return Err(manifest.err().unwrap());
+    }
+
+    let mut manifest = manifest.unwrap();
+    let failed = manifest.failures.remove(reference).is_some();
+    if manifest.commits.remove(reference).is_none() {
+        return if failed {
+            save_manifest(repo, &manifest)
+        } else {
+            Ok(())
+        };
+    }
+
+    let dir = ref_dir(repo, reference);
+    if dir.is_err() {
+        return Err(dir.err().unwrap());
+    }
+
+    if fs::remove_dir_all(dir.unwrap()).is_err() {
+        return Err(StupidDbError::IndexStoreFailed);
+    }
+
+    save_manifest(repo, &manifest)
+}
+
+/// Names of the documents that may match the predicates at a commit, None
+/// when no index reflects the commit or the indexes cover none of the
+/// predicates.
+///
+/// Candidates still have to be matched, predicates on paths that are not
+/// indexed are left to the query.
+pub fn candidates(
+    repo: &StupidRepository,
+    commit: git2::Oid,
+    predicates: &Vec<StupidPredicate>,
+) -> Option<BTreeSet<String>> {
+    let _guard = INDEX_LOCK.read().unwrap();
+    let manifest = load_manifest(repo).ok()?;
+    let commit = commit.to_string();
+    let reference = manifest
+        .commits
+        .iter()
+        .find(|(_, c)| **c == commit)
+        .map(|(r, _)| r)?;
+
+    let mut loaded: HashMap<&String, PathIndex> = HashMap::new();
+    let mut candidates: Option<BTreeSet<String>> = None;
+    for predicate in predicates.iter() {
+        // Elements of arrays are not indexed
+        if let StupidPredicate::Contains { .. } = predicate {
+            continue;
+        }
+
+        let pointer = query::pointer(predicate.path());
+        let path = manifest
+            .paths
+            .iter()
+            .find(|p| query::pointer(p.as_str()) == pointer);
+        if path.is_none() {
+            continue;
+        }
+
+        let path = path.unwrap();
+        if !loaded.contains_key(path) {
+            loaded.insert(path, load_path(repo, reference, path).ok()?);
+        }
+
+        let path_index = loaded.get(path).unwrap();
+        let mut names: BTreeSet<String> = match predicate {
+            StupidPredicate::Eq { value, .. } => path_index
+                .values
+                .get(&key(value))
+                .cloned()
+                .unwrap_or_default(),
+            StupidPredicate::Range { .. } => path_index
+                .values
+                .iter()
+                .filter(|(k, _)| {
+                    serde_json::from_str::<Value>(k.as_str())
+                        .map_or(false, |v| query::matches_value(Some(&v), predicate))
+                })
+                .flat_map(|(_, names)| names.iter().cloned())
+                .collect(),
+            _ => path_index.values.values().flatten().cloned().collect(),
+        };
+        names.extend(path_index.sealed.iter().cloned());
+
+        candidates = Some(match candidates {
+            None => names,
+            Some(c) => c.intersection(&names).cloned().collect(),
+        });
+    }
+
+    candidates
+}
+
+/// Builds every path of the index of a branch from its commit, replacing
+/// the files of the index. The manifest is left for the caller to save.
+fn build(
+    repo: &StupidRepository,
+    manifest: &mut Manifest,
+    reference: &str,
+) -> Result<(), StupidDbError> {
+    let dir = ref_dir(repo, reference);
+    if dir.is_err() {
+        return Err(dir.err().unwrap());
+    }
+
+    let dir = dir.unwrap();
+    if fs::remove_dir_all(&dir)
+        .and_then(|_| fs::create_dir_all(&dir))
+        .is_err()
+    {
+        return Err(StupidDbError::IndexStoreFailed);
+    }
+
+    manifest.commits.remove(reference);
+    let head = repo.find_revision_commit(reference);
+    if head.is_none() {
+        return Ok(());
+    }
+
+    let head = head.unwrap();
+    let tree = head.tree();
+    if tree.is_err() {
+        return Err(StupidDbError::TreeFetchFailed);
+    }
+
+    let mut path_indexes: Vec<PathIndex> = manifest
+        .paths
+        .iter()
+        .map(|_| PathIndex::default())
+        .collect();
+    for entry in tree.unwrap().iter() {
+        let name = entry.name();
+        if name.is_none() || entry.kind() != Some(git2::ObjectType::Blob) {
+            continue;
+        }
+
+        let content = repo.read_stored_value(entry.id());
+        if content.is_err() {
+            return Err(content.err().unwrap());
+        }
+
+        let content = content.unwrap();
+        for (path, path_index) in manifest.paths.iter().zip(path_indexes.iter_mut()) {
+            path_index.add(path, name.unwrap(), &content);
+        }
+    }
+
+    for (path, path_index) in manifest.paths.iter().zip(path_indexes.iter()) {
+        let saved = save_path(repo, reference, path, path_index);
+        if saved.is_err() {
+            return Err(saved.err().unwrap());
+        }
+    }
+
+    manifest
+        .commits
+        .insert(String::from(reference), head.id().to_string());
+    manifest.failures.remove(reference);
+    Ok(())
+}
+
+/// Starts the index of a branch as a copy of the index of another one, the
+/// branch HEAD points at preferably. Nothing is copied when no branch has
+/// an index yet.
+fn seed(
+    repo: &StupidRepository,
+    manifest: &mut Manifest,
+    reference: &str,
+) -> Result<(), StupidDbError> {
+    let head = repo.head_branch();
+    let source = head
+        .as_ref()
+        .filter(|h| manifest.commits.contains_key(*h))
+        .or(manifest.commits.keys().next())
+        .cloned();
+    if source.is_none() {
+        return Ok(());
+    }
+
+    let source = source.unwrap();
+    let dirs = (ref_dir(repo, source.as_str()), ref_dir(repo, reference));
+    if dirs.0.is_err() || dirs.1.is_err() {
+        return Err(StupidDbError::IndexStoreFailed);
+    }
+
+    let (from, to) = (dirs.0.unwrap(), dirs.1.unwrap());
+    for path in manifest.paths.iter() {
+        let file = path_file(path);
+        if from.join(&file).exists() && fs::copy(from.join(&file), to.join(&file)).is_err() {
+            return Err(StupidDbError::IndexStoreFailed);
+        }
+    }
+
+    let commit = manifest.commits.get(&source).cloned().unwrap_or_default();
+    manifest.commits.insert(String::from(reference), commit);
+    Ok(())
+}
+
+/// Whether a change leaves the value at a path, and whether it is sealed,
+/// as it was
+fn unchanged(path: &str, old: Option<&Value>, new: Option<&Value>) -> bool {
+    let old_value = old.and_then(|o| query::lookup(o, path));
+    let new_value = new.and_then(|n| query::lookup(n, path));
+    let old_sealed = old.map_or(false, |o| encryption::is_sealed(o));
+    let new_sealed = new.map_or(false, |n| encryption::is_sealed(n));
+    old_value == new_value && old_sealed == new_sealed
+}
+
+fn load_manifest(repo: &StupidRepository) -> Result<Manifest, StupidDbError> {
+    let dir = index_dir(repo);
+    if dir.is_err() {
+        return Err(dir.err().unwrap());
+    }
+
+    load(dir.unwrap().join(MANIFEST_FILE))
+}
+
+fn save_manifest(repo: &StupidRepository, manifest: &Manifest) -> Result<(), StupidDbError> {
+    let dir = index_dir(repo);
+    if dir.is_err() {
+        return Err(dir.err().unwrap());
+    }
+
+    save(dir.unwrap().join(MANIFEST_FILE), manifest)
+}
+
+fn load_path(
+    repo: &StupidRepository,
+    reference: &str,
+    path: &str,
+) -> Result<PathIndex, StupidDbError> {
+    let dir = ref_dir(repo, reference);
+    if dir.is_err() {
+        return Err(dir.err().unwrap());
+    }
+
+    load(dir.unwrap().join(path_file(path)))
+}
+
+fn save_path(
+    repo: &StupidRepository,
+    reference: &str,
+    path: &str,
+    path_index: &PathIndex,
+) -> Result<(), StupidDbError> {
+    let dir = ref_dir(repo, reference);
+    if dir.is_err() {
+        return Err(dir.err().unwrap());
+    }
+
+    save(dir.unwrap().join(path_file(path)), path_index)
+}
+
+fn load<T: Default + for<'de> Deserialize<'de>>(file: PathBuf) -> Result<T, StupidDbError> {
+    if !file.exists() {
+        return Ok(T::default());
+    }
+
+    fs::read_to_string(file)
+        .ok()
+        .and_then(|c| serde_json::from_str(c.as_str()).ok())
+        .ok_or(StupidDbError::IndexStoreFailed)
+}
+
+fn save<T: Serialize>(file: PathBuf, value: &T) -> Result<(), StupidDbError> {
+    // Written aside and renamed, a query never reads half an index
+    let temporary = file.with_extension("tmp");
+    let content = serde_json::to_string(value).unwrap();
+    fs::write(&temporary, content)
+        .and_then(|_| fs::rename(&temporary, file))
+        .map_err(|_| StupidDbError::IndexStoreFailed)
+}
+
+fn index_dir(repo: &StupidRepository) -> Result<PathBuf, StupidDbError> {
+    let dir = repo.data_dir();
+    if dir.is_err() {
+        return Err(dir.err().unwrap());
+    }
+
+    let dir = dir.unwrap().join(INDEX_DIR);
+    match fs::create_dir_all(&dir) {
+        Ok(_) => Ok(dir),
+        Err(_) => Err(StupidDbError::IndexStoreFailed),
+    }
+}
+
+/// Directory of the files of the index of a branch
+fn ref_dir(repo: &StupidRepository, reference: &str) -> Result<PathBuf, StupidDbError> {
+    let dir = index_dir(repo);
+    if dir.is_err() {
+        return Err(dir.err().unwrap());
+    }
+
+    let dir = dir.unwrap().join(hex_digest(reference));
+    match fs::create_dir_all(&dir) {
+        Ok(_) => Ok(dir),
+        Err(_) => Err(StupidDbError::IndexStoreFailed),
+    }
+}
+
+fn path_file(path: &str) -> String {
+    format!("{}.json", hex_digest(path))
+}
+
+/// Paths and refs hold characters file names cannot, files are named by a
+/// digest
+fn hex_digest(text: &str) -> String {
+    let digest = digest::digest(&digest::SHA256, text.as_bytes());
+    digest
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Values are indexed by their JSON text, equal values share a key
+fn key(value: &Value) -> String {
+    value.to_string()
+}
@@ -1,7 +1,28 @@
+use super::models::{StupidMirrorStatus, StupidRemote};
+
 #[derive(serde::Serialize)]
 pub struct StupidRepositoryInfo {
     pub index_length: i32,
     pub commit_length: i64,
+    pub head: Option<String>,
+    /// Commit time of HEAD in seconds since epoch
+    pub head_time: Option<i64>,
+    /// Bytes the repository takes on disk
+    pub size: u64,
+    pub remotes: Vec<StupidRemote>,
+    /// Commits HEAD is ahead of the branch on origin
+    pub ahead: Option<usize>,
+    /// Commits HEAD is behind the branch on origin
+    pub behind: Option<usize>,
+    pub mirror: Option<StupidMirrorStatus>,
+}
+
+#[derive(serde::Serialize)]
+pub struct StupidRepositorySummary {
+    pub name: String,
+    pub origin: Option<String>,
+    pub head: Option<String>,
+    pub size: u64,
 }
 
 #[derive(serde::Serialize)]
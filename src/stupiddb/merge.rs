@@ -0,0 +1,433 @@
+//! Three-way merge of JSON documents.
+//!
+//! Git merges the single line a document is stored as, so any two changes
+//! to a document conflict. Merging the parsed values instead lets changes
+//! to different fields of the same document merge cleanly.
+
+use super::models::{StupidArrayStrategy, StupidConflictKind};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Default)]
+pub struct MergeOptions {
+    /// How arrays are merged unless overridden for their path
+    pub arrays: StupidArrayStrategy,
+    /// Array strategies by JSON Pointer of the array
+    pub paths: HashMap<String, StupidArrayStrategy>,
+}
+
+/// A value changed differently on both sides
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    pub kind: StupidConflictKind,
+    /// JSON Pointer to the value, empty for the whole document
+    pub path: String,
+    pub base: Option<Value>,
+    pub ours: Option<Value>,
+    pub theirs: Option<Value>,
+}
+
+/// Merges ours and theirs, two versions derived from base.
+///
+/// `None` stands for a value that does not exist on that side. Changes
+/// made on one side only are taken over, values changed differently on
+/// both sides are returned as conflicts. Objects are merged key by key,
+/// arrays as configured in the options.
+pub fn merge(
+    base: Option<&Value>,
+    ours: Option<&Value>,
+    theirs: Option<&Value>,
+    options: &MergeOptions,
+) -> Result<Option<Value>, Vec<Conflict>> {
+    let mut conflicts = Vec::new();
+    let merged = merge_value("", base, ours, theirs, options, &mut conflicts);
+    match conflicts.is_empty() {
+        true => Ok(merged),
+        false => Err(conflicts),
+    }
+}
+
+fn merge_value(
+    path: &str,
+    base: Option<&Value>,
+    ours: Option<&Value>,
+    theirs: Option<&Value>,
+    options: &MergeOptions,
+    conflicts: &mut Vec<Conflict>,
+) -> Option<Value> {
+    if ours == theirs {
+        return ours.cloned();
+    }
+
+    if base == ours {
+        return theirs.cloned();
+    }
+
+    if base == theirs {
+        return ours.cloned();
+    }
+
+    match (ours, theirs) {
+        (Some(Value::Object(o)), Some(Value::Object(t))) => {
+            let empty = Map::new();
+            let b = match base {
+                Some(Value::Object(b)) => b,
+                _ => &empty,
+            };
+            Some(Value::Object(merge_objects(
+                path, b, o, t, options, conflicts,
+            )))
+        }
+        (Some(Value::Array(o)), Some(Value::Array(t))) => {
+            let empty = Vec::new();
+            let b = match base {
+                Some(Value::Array(b)) => b,
+                _ => &empty,
+            };
+            let strategy = options.paths.get(path).unwrap_or(&options.arrays);
+            match merge_arrays(path, strategy, b, o, t, options, conflicts) {
+                Some(merged) => Some(Value::Array(merged)),
+                None => conflict(path, base, ours, theirs, conflicts),
+            }
+        }
+        _ => conflict(path, base, ours, theirs, conflicts),
+    }
+}
+
+fn merge_objects(
+    path: &str,
+    base: &Map<String, Value>,
+    ours: &Map<String, Value>,
+    theirs: &Map<String, Value>,
+    options: &MergeOptions,
+    conflicts: &mut Vec<Conflict>,
+) -> Map<String, Value> {
+    let mut keys: Vec<&String> = ours.keys().collect();
+    for key in theirs.keys().chain(base.keys()) {
+        if !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+
+    let mut merged = Map::new();
+    for key in keys {
+        let child_path = format!("{}/{}", path, escape(key));
+        let value = merge_value(
+            child_path.as_str(),
+            base.get(key),
+            ours.get(key),
+            theirs.get(key),
+            options,
+            conflicts,
+        );
+        if value.is_some() {
+            merged.insert(key.to_string(), value.unwrap());
+        }
+    }
+
+    merged
+}
+
+/// Merges arrays by strategy, None when the strategy cannot merge them
+fn merge_arrays(
+    path: &str,
+    strategy: &StupidArrayStrategy,
+    base: &Vec<Value>,
+    ours: &Vec<Value>,
+    theirs: &Vec<Value>,
+    options: &MergeOptions,
+    conflicts: &mut Vec<Conflict>,
+) -> Option<Vec<Value>> {
+    match strategy {
+        StupidArrayStrategy::Replace => None,
+        StupidArrayStrategy::Union => {
+            // Elements removed on either side stay removed, elements added
+            // on either side are kept with ours first.
+            let merged = ours
+                .iter()
+                .chain(theirs.iter().filter(|v| !ours.contains(v)))
+                .filter(|v| !base.contains(v) || (ours.contains(v) && theirs.contains(v)))
+                .cloned()
+                .collect();
+            Some(merged)
+        }
+        StupidArrayStrategy::ById { key } => {
+            let id = |v: &Value| v.get(key.as_str()).cloned();
+            if base
+                .iter()
+                .chain(ours)
+                .chain(theirs)
+                .any(|v| id(v).is_none())
+            {
+                return None;
+            }
+
+            let find = |items: &Vec<Value>, item_id: &Value| {
+                items
+                    .iter()
+                    .find(|v| id(v).as_ref() == Some(item_id))
+                    .cloned()
+            };
+
+            let mut ids: Vec<Value> = Vec::new();
+            for item in ours.iter().chain(theirs).chain(base) {
+                let item_id = id(item).unwrap();
+                if !ids.contains(&item_id) {
+                    ids.push(item_id);
+                }
+            }
+
+            let mut merged = Vec::new();
+            for item_id in ids.iter() {
+                let child_path = format!("{}/{}", path, escape(id_token(item_id).as_str()));
+                let value = merge_value(
+                    child_path.as_str(),
+                    find(base, item_id).as_ref(),
+                    find(ours, item_id).as_ref(),
+                    find(theirs, item_id).as_ref(),
+                    options,
+                    conflicts,
+                );
+                if value.is_some() {
+                    merged.push(value.unwrap());
+                }
+            }
+
+            Some(merged)
+        }
+    }
+}
+
+fn conflict(
+    path: &str,
+    base: Option<&Value>,
+    ours: Option<&Value>,
+    theirs: Option<&Value>,
+    conflicts: &mut Vec<Conflict>,
+) -> Option<Value> {
+    let kind = match (base, ours, theirs) {
+        (None, _, _) => StupidConflictKind::AddAdd,
+        (_, None, _) | (_, _, None) => StupidConflictKind::DeleteModify,
+        _ => StupidConflictKind::ModifyModify,
+    };
+
+    conflicts.push(Conflict {
+        kind,
+        path: String::from(path),
+        base: base.cloned(),
+        ours: ours.cloned(),
+        theirs: theirs.cloned(),
+    });
+
+    ours.cloned()
+}
+
+/// Array elements merged by id are addressed by their id in paths
+fn id_token(id: &Value) -> String {
+    match id {
+        Value::String(s) => s.to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Escapes a key for use as JSON Pointer reference token
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn with_arrays(arrays: StupidArrayStrategy) -> MergeOptions {
+        MergeOptions {
+            arrays,
+            ..MergeOptions::default()
+        }
+    }
+
+    fn merged(base: Value, ours: Value, theirs: Value, options: &MergeOptions) -> Value {
+        merge(Some(&base), Some(&ours), Some(&theirs), options)
+            .unwrap()
+            .unwrap()
+    }
+
+    fn conflicts(base: Value, ours: Value, theirs: Value, options: &MergeOptions) -> Vec<Conflict> {
+        merge(Some(&base), Some(&ours), Some(&theirs), options).unwrap_err()
+    }
+
+    #[test]
+    fn takes_scalar_changed_on_one_side() {
+        let options = MergeOptions::default();
+        assert_eq!(merged(json!(1), json!(1), json!(2), &options), json!(2));
+        assert_eq!(merged(json!(1), json!(2), json!(1), &options), json!(2));
+        assert_eq!(merged(json!(1), json!(3), json!(3), &options), json!(3));
+    }
+
+    #[test]
+    fn conflicts_on_scalar_changed_on_both_sides() {
+        let found = conflicts(json!(1), json!(2), json!(3), &MergeOptions::default());
+        assert_eq!(
+            found,
+            vec![Conflict {
+                kind: StupidConflictKind::ModifyModify,
+                path: String::new(),
+                base: Some(json!(1)),
+                ours: Some(json!(2)),
+                theirs: Some(json!(3)),
+            }]
+        );
+    }
+
+    #[test]
+    fn merges_objects_key_by_key() {
+        let result = merged(
+            json!({"a": 1, "b": 1, "c": 1, "nested": {"x": 1, "y": 1}}),
+            json!({"a": 2, "b": 1, "nested": {"x": 2, "y": 1}}),
+            json!({"a": 1, "b": 3, "c": 1, "d": 4, "nested": {"x": 1, "y": 5}}),
+            &MergeOptions::default(),
+        );
+        assert_eq!(
+            result,
+            json!({"a": 2, "b": 3, "d": 4, "nested": {"x": 2, "y": 5}})
+        );
+    }
+
+    #[test]
+    fn reports_nested_conflicts_with_their_path() {
+        let found = conflicts(
+            json!({"a/b": {"c": 1}}),
+            json!({"a/b": {"c": 2}}),
+            json!({"a/b": {"c": 3}}),
+            &MergeOptions::default(),
+        );
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].path, "/a~1b/c");
+        assert_eq!(found[0].kind, StupidConflictKind::ModifyModify);
+    }
+
+    #[test]
+    fn conflicts_on_delete_and_modify() {
+        let found = conflicts(
+            json!({"a": 1}),
+            json!({}),
+            json!({"a": 2}),
+            &MergeOptions::default(),
+        );
+        assert_eq!(found[0].kind, StupidConflictKind::DeleteModify);
+        assert_eq!(found[0].ours, None);
+        assert_eq!(found[0].theirs, Some(json!(2)));
+    }
+
+    #[test]
+    fn conflicts_on_documents_added_on_both_sides() {
+        let found = merge(
+            None,
+            Some(&json!({"a": 1})),
+            Some(&json!({"a": 2})),
+            &MergeOptions::default(),
+        )
+        .unwrap_err();
+        assert_eq!(found[0].kind, StupidConflictKind::AddAdd);
+        assert_eq!(found[0].path, "/a");
+    }
+
+    #[test]
+    fn removes_document_deleted_on_one_side() {
+        let value = json!({"a": 1});
+        let result = merge(Some(&value), None, Some(&value), &MergeOptions::default());
+        assert_eq!(result, Ok(None));
+    }
+
+    #[test]
+    fn replace_conflicts_on_arrays_changed_on_both_sides() {
+        let options = with_arrays(StupidArrayStrategy::Replace);
+        let found = conflicts(
+            json!({"tags": ["a"]}),
+            json!({"tags": ["a", "b"]}),
+            json!({"tags": ["a", "c"]}),
+            &options,
+        );
+        assert_eq!(found[0].path, "/tags");
+        assert_eq!(
+            merged(json!(["a"]), json!(["a"]), json!(["b"]), &options),
+            json!(["b"])
+        );
+    }
+
+    #[test]
+    fn union_keeps_additions_and_drops_removals() {
+        let result = merged(
+            json!(["a", "b", "c"]),
+            json!(["a", "b", "d"]),
+            json!(["b", "c", "e"]),
+            &with_arrays(StupidArrayStrategy::Union),
+        );
+        assert_eq!(result, json!(["b", "d", "e"]));
+    }
+
+    #[test]
+    fn by_id_merges_elements_matched_by_key() {
+        let options = with_arrays(StupidArrayStrategy::ById {
+            key: String::from("id"),
+        });
+        let result = merged(
+            json!([{"id": 1, "n": 1}, {"id": 2, "n": 1}]),
+            json!([{"id": 1, "n": 1}, {"id": 2, "n": 2}, {"id": 3, "n": 1}]),
+            json!([{"id": 2, "n": 1}, {"id": 4, "n": 1}]),
+            &options,
+        );
+        assert_eq!(
+            result,
+            json!([{"id": 2, "n": 2}, {"id": 3, "n": 1}, {"id": 4, "n": 1}])
+        );
+    }
+
+    #[test]
+    fn by_id_conflicts_within_elements() {
+        let options = with_arrays(StupidArrayStrategy::ById {
+            key: String::from("id"),
+        });
+        let found = conflicts(
+            json!({"items": [{"id": "x", "n": 1}]}),
+            json!({"items": [{"id": "x", "n": 2}]}),
+            json!({"items": [{"id": "x", "n": 3}]}),
+            &options,
+        );
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].path, "/items/x/n");
+    }
+
+    #[test]
+    fn by_id_falls_back_to_conflict_without_ids() {
+        let options = with_arrays(StupidArrayStrategy::ById {
+            key: String::from("id"),
+        });
+        let found = conflicts(json!([1]), json!([1, 2]), json!([1, 3]), &options);
+        assert_eq!(found[0].path, "");
+        assert_eq!(found[0].kind, StupidConflictKind::ModifyModify);
+    }
+
+    #[test]
+    fn strategy_of_path_overrides_default() {
+        let mut options = with_arrays(StupidArrayStrategy::Replace);
+        options
+            .paths
+            .insert(String::from("/tags"), StupidArrayStrategy::Union);
+        let result = merged(
+            json!({"tags": ["a"], "other": ["a"]}),
+            json!({"tags": ["a", "b"], "other": ["a"]}),
+            json!({"tags": ["a", "c"], "other": ["a"]}),
+            &options,
+        );
+        assert_eq!(result, json!({"tags": ["a", "b", "c"], "other": ["a"]}));
+        assert!(merge(
+            Some(&json!({"other": ["a"]})),
+            Some(&json!({"other": ["b"]})),
+            Some(&json!({"other": ["c"]})),
+            &options
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,97 @@
+use super::cache::SharedCache;
+use super::config::SharedConfig;
+use super::error::StupidDbError;
+use super::models::StupidMirrorStatus;
+use super::repository::StupidRepository;
+use super::StupidDb;
+use std::collections::HashMap;
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const STATUS_FILE: &str = "mirror.json";
+const TICK: Duration = Duration::from_secs(5);
+
+/// Starts the scheduler synchronizing mirror repositories in the background
+pub fn start(config: &SharedConfig, cache: &SharedCache) {
+    let config = config.clone();
+    let cache = cache.clone();
+    thread::spawn(move || {
+        let mut last_runs: HashMap<String, Instant> = HashMap::new();
+        loop {
+            let names = StupidDb::new(&config, &cache).names();
+            for name in names.iter() {
+                let mut db = StupidDb::new(&config, &cache);
+                let repository = db.open(name);
+                if repository.is_err() {
+                    continue;
+                }
+
+                let repo = repository.unwrap();
+                if repo.mirror.is_none() {
+                    last_runs.remove(name);
+                    continue;
+                }
+
+                let interval = Duration::from_secs(repo.mirror.as_ref().unwrap().interval);
+                let due = last_runs
+                    .get(name)
+                    .map_or(true, |last| last.elapsed() >= interval);
+                if !due {
+                    continue;
+                }
+
+                last_runs.insert(name.to_string(), Instant::now());
+                let result = repo.sync_mirror();
+                if result.is_err() {
+                    eprintln!(
+                        "Mirror {} failed to sync: {:?}",
+                        name,
+                        result.as_ref().err()
+                    );
+                }
+
+                let _ = record(repo, result);
+            }
+
+            last_runs.retain(|name, _| names.contains(name));
+            thread::sleep(TICK);
+        }
+    });
+}
+
+/// Last synchronization status of a mirror repository
+pub fn status(repo: &StupidRepository) -> Option<StupidMirrorStatus> {
+    let dir = repo.data_dir().ok()?;
+    let content = fs::read_to_string(dir.join(STATUS_FILE)).ok()?;
+    serde_json::from_str(content.as_str()).ok()
+}
+
+fn record(
+    repo: &StupidRepository,
+    result: Result<git2::Oid, StupidDbError>,
+) -> Result<(), StupidDbError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .ok();
+
+    let mut status = status(repo).unwrap_or_default();
+    status.last_attempt = now;
+    match result {
+        Ok(head) => {
+            status.last_sync = now;
+            status.last_error = None;
+            status.head = Some(head.to_string());
+        }
+        Err(e) => status.last_error = Some(format!("{:?}", e)),
+    }
+
+    let dir = repo.data_dir();
+    if dir.is_err() {
+        return Err(dir.err().unwrap());
+    }
+
+    let content = serde_json::to_string(&status).unwrap();
+    fs::write(dir.unwrap().join(STATUS_FILE), content).map_err(|_| StupidDbError::RepoPathInvalid)
+}
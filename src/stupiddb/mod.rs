@@ -1,23 +1,48 @@
+pub mod auth;
+pub mod autopush;
+pub mod cache;
+pub mod config;
+pub mod credentials;
+pub mod encryption;
 pub mod error;
+pub mod events;
+mod history;
+pub mod index;
 pub mod info;
+pub mod merge;
+pub mod mirror;
 pub(super) mod models;
+pub mod query;
 pub(super) mod regex;
 pub mod repository;
+pub mod search;
+mod signing;
+pub mod webhook;
+
+/// Directory under the root dir deleted repositories are moved to
+const ARCHIVE_DIR: &str = ".archive";
 
 pub struct StupidDb {
     pub instance_id: String,
     pub root_dir: String,
+    config: config::SharedConfig,
+    cache: cache::SharedCache,
     repo: Option<repository::StupidRepository>,
+    /// Cache generation of the repository when it was opened
+    generation: u64,
 }
 
 impl StupidDb {
-    pub fn new() -> StupidDb {
-        let cfg: models::StupidConfig = confy::load("stupiddb").unwrap();
+    pub fn new(config: &config::SharedConfig, cache: &cache::SharedCache) -> StupidDb {
         let instance_id = uuid::Uuid::new_v4().to_string();
+        let root_dir = config.read().unwrap().root_dir.clone();
         StupidDb {
             instance_id,
-            root_dir: cfg.root_dir,
+            root_dir,
+            config: config.clone(),
+            cache: cache.clone(),
             repo: None,
+            generation: 0,
         }
     }
 
@@ -37,8 +62,6 @@ impl StupidDb {
         let index_entry =
             models::StupidEntry::new(blob.oid.unwrap().clone(), blob.name.clone().into_bytes());
 
-        println!("{}", blob.oid.as_ref().unwrap());
-
         let add_result = index.add(&index_entry.entry).map_err(|e| {
             println!("{}", e);
             return error::StupidDbError::BlobFailed;
@@ -85,6 +108,23 @@ impl StupidDb {
         self.repo.as_ref().unwrap().commit(tree_oid, blob)
     }
 
+    /// Represents git commit-tree onto a branch, building the tree from
+    /// the branch instead of the index
+    pub fn commit_to_branch(
+        &self,
+        branch: &String,
+        blob: &models::StupidBlob,
+    ) -> Result<git2::Oid, error::StupidDbError> {
+        if !self.repo.is_some() {
+            return Err(error::StupidDbError::RepoNotInitialized);
+        }
+
+        self.repo
+            .as_ref()
+            .unwrap()
+            .commit_to_branch(branch.as_str(), blob)
+    }
+
     /// Represents git hash-object --stdin
     pub fn hash_object(
         &self,
@@ -107,7 +147,8 @@ impl StupidDb {
         Ok(new_blob)
     }
 
-    /// Represents git hash-object --stdin -w
+    /// Represents git hash-object --stdin -w, storing ciphertext for the
+    /// values the encryption rules of the repository cover
     pub fn hash_object_write(
         &self,
         blob: &models::StupidBlob,
@@ -117,7 +158,12 @@ impl StupidDb {
             return Err(result.err().unwrap());
         }
         let repo = self.repo.as_ref().unwrap();
-        let blob_result = repo.blob(blob.content.to_string().as_bytes());
+        let sealed = encryption::seal(repo, blob.name.as_str(), &blob.content);
+        if sealed.is_err() {
+            return Err(sealed.err().unwrap());
+        }
+
+        let blob_result = repo.blob(sealed.unwrap().to_string().as_bytes());
 
         if blob_result.is_err() {
             return Err(error::StupidDbError::BlobFailed);
@@ -129,6 +175,72 @@ impl StupidDb {
         Ok(new_blob)
     }
 
+    /// Writes a document and commits it onto HEAD, or onto the given branch
+    /// when HEAD points at another one
+    pub fn write(
+        &self,
+        blob: &models::StupidBlob,
+        branch: Option<&str>,
+    ) -> Result<models::StupidBlob, error::StupidDbError> {
+        if !self.repo.is_some() {
+            return Err(error::StupidDbError::RepoNotInitialized);
+        }
+
+        // Writes to the branch HEAD points at go through the index
+        let repo = self.repo.as_ref().unwrap();
+        let other_branch = branch.filter(|b| !repo.is_head_branch(b));
+
+        let hashed_blob = self.hash_object_write(blob);
+        if hashed_blob.is_err() {
+            return Err(hashed_blob.err().unwrap());
+        }
+
+        if other_branch.is_some() {
+            let commit_error = self
+                .commit_to_branch(
+                    &other_branch.unwrap().to_string(),
+                    hashed_blob.as_ref().unwrap(),
+                )
+                .err();
+            if commit_error.is_some() {
+                return Err(commit_error.unwrap());
+            }
+
+            return hashed_blob;
+        }
+
+        let update_error = self.update_index(hashed_blob.as_ref().unwrap()).err();
+        if update_error.is_some() {
+            return Err(update_error.unwrap());
+        }
+
+        let write_tree_result = self.write_tree();
+        if write_tree_result.is_err() {
+            return Err(write_tree_result.err().unwrap());
+        }
+
+        let commit_error = self.commit_tree(write_tree_result.unwrap(), blob).err();
+        if commit_error.is_some() {
+            return Err(commit_error.unwrap());
+        }
+
+        hashed_blob
+    }
+
+    /// Removes a document from HEAD
+    pub fn remove(&self, name: &str, message: Option<&str>) -> Result<(), error::StupidDbError> {
+        if !self.repo.is_some() {
+            return Err(error::StupidDbError::RepoNotInitialized);
+        }
+
+        let message = message.map_or(format!("Remove {}", name), String::from);
+        self.repo
+            .as_ref()
+            .unwrap()
+            .remove(name, message.as_str())
+            .map(|_| ())
+    }
+
     /// Opens a previously created repository
     pub fn open(
         &mut self,
@@ -138,16 +250,38 @@ impl StupidDb {
             return Ok(self.repo.as_ref().unwrap());
         }
 
-        let path = self.get_repo_full_path(name);
-        let mut stupidrepo = repository::StupidRepository::new(path);
+        if !self::regex::NAME.is_match(name) {
+            return Err(error::StupidDbError::RepoPathInvalid);
+        }
 
-        let res = stupidrepo.open();
-        if res.is_ok() {
-            self.repo = Some(stupidrepo);
-            return Ok(self.repo.as_ref().unwrap());
+        let (cached, generation) = self.cache.take(name);
+        self.generation = generation;
+        let mut stupidrepo = match cached {
+            Some(repo) => repo,
+            None => {
+                let path = self.get_repo_full_path(name);
+                let mut stupidrepo = repository::StupidRepository::new(path);
+                let res = stupidrepo.open();
+                if res.is_err() {
+                    return Err(res.err().unwrap());
+                }
+
+                stupidrepo
+            }
+        };
+
+        // Credentials, committer and keys may have changed while the repository
+        // sat in the cache
+        {
+            let config = self.config.read().unwrap();
+            stupidrepo.credentials = config.credentials.get(name).cloned();
+            stupidrepo.committer = config.committer.clone();
+            stupidrepo.signing = config.signing.clone();
+            stupidrepo.encryption = config.encryption.clone();
         }
 
-        return Err(res.err().unwrap());
+        self.repo = Some(stupidrepo);
+        Ok(self.repo.as_ref().unwrap())
     }
 
     /// Represents git init but with origin if given
@@ -160,21 +294,161 @@ impl StupidDb {
         if config.origin.is_some() {
             stupidrepo.origin = Some(String::from(config.origin.as_ref().unwrap().as_str()));
         }
+        {
+            let config = self.config.read().unwrap();
+            stupidrepo.committer = config.committer.clone();
+            stupidrepo.signing = config.signing.clone();
+            stupidrepo.encryption = config.encryption.clone();
+        }
+
+        // The new handle replaces the one cached for the name, if any
+        let (_, generation) = self.cache.take(&config.name);
+        self.generation = generation;
 
         if stupidrepo.open().is_err() {
             let result = stupidrepo.init();
             if result.is_err() {
                 return result;
             }
+
+            // init only creates the repository on disk, a handle kept
+            // without opening it fails every call with RepoNotInitialized
+            let open_result = stupidrepo.open();
+            if open_result.is_err() {
+                return Err(open_result.err().unwrap());
+            }
         }
 
         self.repo = Some(stupidrepo);
         Ok(())
     }
 
+    /// Stores the remote credentials of a repository in the server config
+    pub fn set_credentials(
+        &mut self,
+        name: &String,
+        credentials: &models::StupidCredentials,
+    ) -> Result<(), error::StupidDbError> {
+        let store_result = config::store_credentials(&self.config, name, Some(credentials));
+        if store_result.is_err() {
+            return store_result;
+        }
+
+        if self.repo.is_some() {
+            self.repo.as_mut().unwrap().credentials = Some(credentials.clone());
+        }
+
+        Ok(())
+    }
+
+    /// Names of all repositories under the root dir
+    pub fn names(&self) -> Vec<String> {
+        let entries = std::fs::read_dir(self.root_dir.as_str());
+        if entries.is_err() {
+            return Vec::new();
+        }
+
+        let mut names: Vec<String> = entries
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().is_dir())
+            .filter_map(|e| e.file_name().into_string().ok())
+            .filter(|n| self::regex::NAME.is_match(n))
+            .collect();
+
+        names.sort();
+        names
+    }
+
+    /// Summaries of all repositories under the root dir
+    pub fn list(&self) -> Vec<info::StupidRepositorySummary> {
+        self.names()
+            .iter()
+            .filter_map(|name| {
+                let mut stupidrepo =
+                    repository::StupidRepository::new(self.get_repo_full_path(name));
+                if stupidrepo.open().is_err() {
+                    return None;
+                }
+
+                Some(stupidrepo.summary(name.as_str()))
+            })
+            .collect()
+    }
+
+    /// Removes a repository, moving it to the archive unless purged
+    pub fn delete(&mut self, name: &String, purge: bool) -> Result<(), error::StupidDbError> {
+        if !self::regex::NAME.is_match(name) {
+            return Err(error::StupidDbError::RepoPathInvalid);
+        }
+
+        let path = self.get_repo_full_path(name);
+        if !std::path::Path::new(path.as_str()).is_dir() {
+            return Err(error::StupidDbError::RepoPathInvalid);
+        }
+
+        self.repo = None;
+        self.cache.invalidate(name);
+        if purge {
+            return std::fs::remove_dir_all(path.as_str())
+                .map_err(|_| error::StupidDbError::RepoDeleteFailed);
+        }
+
+        let archive_dir = std::path::Path::new(self.root_dir.as_str()).join(ARCHIVE_DIR);
+        if std::fs::create_dir_all(&archive_dir).is_err() {
+            return Err(error::StupidDbError::RepoDeleteFailed);
+        }
+
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let archived = archive_dir.join(format!("{}-{}", name, timestamp));
+        std::fs::rename(path.as_str(), archived).map_err(|_| error::StupidDbError::RepoDeleteFailed)
+    }
+
+    /// Renames a repository and moves its credentials along
+    pub fn rename(&mut self, name: &String, new_name: &String) -> Result<(), error::StupidDbError> {
+        if !self::regex::NAME.is_match(name) || !self::regex::NAME.is_match(new_name) {
+            return Err(error::StupidDbError::RepoPathInvalid);
+        }
+
+        let path = self.get_repo_full_path(name);
+        if !std::path::Path::new(path.as_str()).is_dir() {
+            return Err(error::StupidDbError::RepoPathInvalid);
+        }
+
+        let new_path = self.get_repo_full_path(new_name);
+        if std::path::Path::new(new_path.as_str()).exists() {
+            return Err(error::StupidDbError::RepoExists);
+        }
+
+        self.repo = None;
+        self.cache.invalidate(name);
+        self.cache.invalidate(new_name);
+        if std::fs::rename(path.as_str(), new_path.as_str()).is_err() {
+            return Err(error::StupidDbError::RepoRenameFailed);
+        }
+
+        let credentials = self.config.read().unwrap().credentials.get(name).cloned();
+        match credentials {
+            None => Ok(()),
+            Some(credentials) => config::store_credentials(&self.config, name, None)
+                .and_then(|_| self.set_credentials(new_name, &credentials)),
+        }
+    }
+
     fn get_repo_full_path(&self, name: &String) -> String {
         let mut s = self.root_dir.to_string();
         s.push_str(name.as_str());
         s
     }
 }
+
+impl Drop for StupidDb {
+    /// Hands the opened repository back to the cache
+    fn drop(&mut self) {
+        if let Some(repo) = self.repo.take() {
+            self.cache.give_back(repo, self.generation);
+        }
+    }
+}
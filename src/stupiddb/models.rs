@@ -1,6 +1,9 @@
+use super::query;
+use super::regex::{DOCUMENTS, NAME, REFSPEC};
 use serde::Deserialize;
 use serde::Serialize;
-use validator::Validate;
+use std::collections::HashMap;
+use validator::{Validate, ValidationError};
 
 #[derive(Deserialize, Serialize, Debug, Validate)]
 pub struct StupidDbRepositoryConfig {
@@ -8,6 +11,627 @@ pub struct StupidDbRepositoryConfig {
     pub name: String,
     #[validate(regex = "crate::stupiddb::regex::ORIGIN")]
     pub origin: Option<String>,
+    #[serde(default, skip_serializing)]
+    pub credentials: Option<StupidCredentials>,
+    /// Refspecs pushed to origin when a push request names none
+    #[validate(custom = "validate_refspecs")]
+    pub push_refspecs: Option<Vec<String>>,
+    /// Remotes besides origin, all remotes when read back
+    #[serde(default)]
+    #[validate(custom = "validate_remotes")]
+    pub remotes: Vec<StupidRemote>,
+    /// Keeps the repository synchronized from a remote, rejecting writes
+    #[validate(custom = "validate_mirror")]
+    pub mirror: Option<StupidMirror>,
+    #[serde(default, skip_deserializing)]
+    pub mirror_status: Option<StupidMirrorStatus>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Validate)]
+pub struct StupidMirror {
+    /// Seconds between two synchronizations
+    #[validate(range(min = 10))]
+    pub interval: u64,
+    /// Remote to synchronize from, origin when not given
+    #[validate(regex = "crate::stupiddb::regex::REMOTE_NAME")]
+    pub remote: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct StupidMirrorStatus {
+    /// Seconds since epoch of the last successful synchronization
+    pub last_sync: Option<u64>,
+    pub last_attempt: Option<u64>,
+    pub last_error: Option<String>,
+    pub head: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Validate)]
+pub struct StupidRemote {
+    #[validate(regex = "crate::stupiddb::regex::REMOTE_NAME")]
+    pub name: String,
+    #[validate(regex = "crate::stupiddb::regex::ORIGIN")]
+    pub url: String,
+    #[validate(custom = "validate_refspecs")]
+    pub push_refspecs: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Validate)]
+pub struct StupidRenameRequest {
+    #[validate(regex = "crate::stupiddb::regex::NAME")]
+    pub name: String,
+}
+
+/// Credentials used to authenticate against a repository's remotes.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StupidCredentials {
+    SshAgent {
+        username: Option<String>,
+    },
+    SshKey {
+        username: Option<String>,
+        private_key: String,
+        public_key: Option<String>,
+        passphrase: Option<String>,
+    },
+    /// HTTPS username with a password or access token
+    UserPass {
+        username: String,
+        password: String,
+    },
+    /// Looks up credentials through the configured git credential helper
+    Helper {
+        username: Option<String>,
+    },
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Validate)]
+pub struct StupidPushRequest {
+    /// Remote to push to, origin when not given
+    #[validate(regex = "crate::stupiddb::regex::REMOTE_NAME")]
+    pub remote: Option<String>,
+    /// Pushes to every remote of the repository
+    #[serde(default)]
+    pub all_remotes: bool,
+    #[validate(custom = "validate_refspecs")]
+    pub refspecs: Option<Vec<String>>,
+    /// Pushes all tags along with the refspecs
+    #[serde(default)]
+    pub tags: bool,
+    /// Refs to force push, by the commit id they are expected to have on
+    /// the remote. An empty id expects the ref not to exist.
+    #[serde(default)]
+    #[validate(custom = "validate_leases")]
+    pub force_with_lease: HashMap<String, String>,
+}
+
+fn validate_refspecs(refspecs: &Vec<String>) -> Result<(), ValidationError> {
+    match refspecs.iter().all(|r| REFSPEC.is_match(r)) {
+        true => Ok(()),
+        false => Err(ValidationError::new("refspec")),
+    }
+}
+
+fn validate_remotes(remotes: &Vec<StupidRemote>) -> Result<(), ValidationError> {
+    match remotes.iter().all(|r| r.validate().is_ok()) {
+        true => Ok(()),
+        false => Err(ValidationError::new("remote")),
+    }
+}
+
+fn validate_mirror(mirror: &StupidMirror) -> Result<(), ValidationError> {
+    match mirror.validate() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(ValidationError::new("mirror")),
+    }
+}
+
+fn validate_predicates(predicates: &Vec<StupidPredicate>) -> Result<(), ValidationError> {
+    match predicates
+        .iter()
+        .all(|p| query::pointer(p.path()).is_some())
+    {
+        true => Ok(()),
+        false => Err(ValidationError::new("path")),
+    }
+}
+
+fn validate_paths(paths: &Vec<String>) -> Result<(), ValidationError> {
+    match paths.iter().all(|p| query::pointer(p).is_some()) {
+        true => Ok(()),
+        false => Err(ValidationError::new("path")),
+    }
+}
+
+fn validate_encryption_rules(rules: &Vec<StupidEncryptionRule>) -> Result<(), ValidationError> {
+    let valid = rules.iter().all(|r| {
+        DOCUMENTS.is_match(r.documents.as_str())
+            && r.fields
+                .iter()
+                .all(|f| !f.is_empty() && query::pointer(f).is_some())
+    });
+    match valid {
+        true => Ok(()),
+        false => Err(ValidationError::new("rule")),
+    }
+}
+
+fn validate_server(server: &StupidServerConfig) -> Result<(), ValidationError> {
+    match server.validate() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(ValidationError::new("server")),
+    }
+}
+
+fn validate_limits(limits: &StupidLimits) -> Result<(), ValidationError> {
+    match limits.validate() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(ValidationError::new("limits")),
+    }
+}
+
+fn validate_identity(identity: &StupidIdentity) -> Result<(), ValidationError> {
+    match identity.validate() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(ValidationError::new("identity")),
+    }
+}
+
+fn validate_roles(roles: &HashMap<String, StupidRole>) -> Result<(), ValidationError> {
+    let valid = roles.keys().all(|key| {
+        let mut parts = key.splitn(2, DOCUMENTS_SEPARATOR);
+        let repository = parts.next().unwrap_or_default();
+        let documents = parts.next();
+        (repository == ALL_REPOSITORIES || NAME.is_match(repository))
+            && documents.map_or(true, |d| DOCUMENTS.is_match(d))
+    });
+    match valid {
+        true => Ok(()),
+        false => Err(ValidationError::new("repository")),
+    }
+}
+
+fn validate_leases(leases: &HashMap<String, String>) -> Result<(), ValidationError> {
+    match leases
+        .keys()
+        .all(|r| r.starts_with("refs/") && !r.contains(':') && REFSPEC.is_match(r))
+    {
+        true => Ok(()),
+        false => Err(ValidationError::new("refspec")),
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StupidRemotePushResult {
+    pub remote: String,
+    pub error: Option<String>,
+    pub references: Vec<StupidPushResult>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StupidPushResult {
+    pub reference: String,
+    /// Rejection message of the remote, None when the ref was updated
+    pub status: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StupidBranch {
+    pub name: String,
+    pub head: Option<String>,
+    /// Whether HEAD points at the branch
+    pub current: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, Validate)]
+pub struct StupidBranchRequest {
+    #[validate(regex = "crate::stupiddb::regex::REF_NAME")]
+    pub name: String,
+    /// Revision to branch off, HEAD when not given
+    pub from: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Validate)]
+pub struct StupidIdentity {
+    #[validate(length(min = 1))]
+    pub name: String,
+    #[validate(email)]
+    pub email: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StupidTag {
+    pub name: String,
+    /// Commit the tag points at
+    pub target: Option<String>,
+    pub message: Option<String>,
+    pub tagger: Option<StupidIdentity>,
+    pub time: Option<i64>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Validate)]
+pub struct StupidTagRequest {
+    #[validate(regex = "crate::stupiddb::regex::REF_NAME")]
+    pub name: String,
+    /// Revision to tag, HEAD when not given
+    pub target: Option<String>,
+    #[validate(length(min = 1))]
+    pub message: String,
+    /// Tagger identity, the repository's signature when not given
+    #[validate(custom = "validate_identity")]
+    pub tagger: Option<StupidIdentity>,
+    /// Replaces an existing tag of the same name
+    #[serde(default)]
+    pub force: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, Validate)]
+pub struct StupidMergeRequest {
+    #[validate(regex = "crate::stupiddb::regex::REF_NAME")]
+    pub source: String,
+    #[validate(regex = "crate::stupiddb::regex::REF_NAME")]
+    pub target: String,
+    pub message: Option<String>,
+    /// How arrays changed on both sides are merged
+    #[serde(default)]
+    pub arrays: StupidArrayStrategy,
+    /// Array strategies by JSON Pointer of the array within its document
+    #[serde(default)]
+    pub array_paths: HashMap<String, StupidArrayStrategy>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "strategy", rename_all = "snake_case")]
+pub enum StupidArrayStrategy {
+    /// Arrays are values of their own, changes on both sides conflict
+    Replace,
+    /// Keeps elements added on either side and drops removed ones
+    Union,
+    /// Merges arrays of objects element by element, matched by a key
+    ById { key: String },
+}
+
+impl Default for StupidArrayStrategy {
+    fn default() -> Self {
+        StupidArrayStrategy::Replace
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StupidConflictKind {
+    ModifyModify,
+    DeleteModify,
+    AddAdd,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StupidMergeStatus {
+    UpToDate,
+    FastForward,
+    Merged,
+    Conflict,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StupidMergeResult {
+    pub status: StupidMergeStatus,
+    /// Commit the target branch points at after the merge
+    pub commit: Option<String>,
+    pub conflicts: Vec<StupidConflict>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StupidRevertResult {
+    /// The new commit, None when the revert conflicts with later changes
+    pub commit: Option<String>,
+    pub conflicts: Vec<StupidConflict>,
+}
+
+/// A field changed differently on both sides of a merge
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StupidConflict {
+    pub document: String,
+    pub kind: StupidConflictKind,
+    /// JSON Pointer to the field, empty for the whole document
+    pub path: String,
+    pub base: Option<serde_json::Value>,
+    pub ours: Option<serde_json::Value>,
+    pub theirs: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StupidChangeEvent {
+    pub repository: String,
+    pub commit: String,
+    /// Ref the commit was made on
+    pub reference: Option<String>,
+    pub message: Option<String>,
+    /// Commit time in seconds since epoch
+    pub time: i64,
+    pub changes: Vec<StupidChange>,
+    /// Verification of the commit signature, only given in history
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<StupidSignatureStatus>,
+}
+
+/// Whether a commit was signed by a key the server trusts
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StupidSignatureStatus {
+    Unsigned,
+    Good,
+    Bad,
+    /// Signed by a key the server does not know, or that it cannot check
+    Unknown,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StupidChange {
+    pub name: String,
+    pub kind: StupidChangeKind,
+    /// Blob id of the document after the change, None when deleted
+    pub id: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StupidChangeKind {
+    Added,
+    Modified,
+    Deleted,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Validate)]
+pub struct StupidQuery {
+    /// Predicates a document has to match all of
+    #[serde(default)]
+    #[validate(custom = "validate_predicates")]
+    pub filter: Vec<StupidPredicate>,
+    /// Paths of the fields returned, whole documents when not given
+    #[validate(custom = "validate_paths")]
+    pub fields: Option<Vec<String>>,
+    #[validate(range(min = 1))]
+    pub limit: Option<usize>,
+}
+
+/// A condition on the value at a JSON Pointer or JSONPath
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum StupidPredicate {
+    Eq {
+        path: String,
+        value: serde_json::Value,
+    },
+    /// Numbers or strings within the given bounds
+    Range {
+        path: String,
+        gt: Option<serde_json::Value>,
+        gte: Option<serde_json::Value>,
+        lt: Option<serde_json::Value>,
+        lte: Option<serde_json::Value>,
+    },
+    Exists {
+        path: String,
+    },
+    /// Arrays holding the value or strings holding the substring
+    Contains {
+        path: String,
+        value: serde_json::Value,
+    },
+}
+
+impl StupidPredicate {
+    pub fn path(&self) -> &str {
+        match self {
+            StupidPredicate::Eq { path, .. } => path.as_str(),
+            StupidPredicate::Range { path, .. } => path.as_str(),
+            StupidPredicate::Exists { path } => path.as_str(),
+            StupidPredicate::Contains { path, .. } => path.as_str(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StupidQueryHit {
+    pub name: String,
+    pub id: String,
+    /// The document, or the selected fields by their path
+    pub content: serde_json::Value,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Validate)]
+pub struct StupidIndexConfig {
+    /// JSON Pointers or JSONPaths of the indexed fields
+    #[validate(custom = "validate_paths")]
+    pub paths: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StupidIndexStatus {
+    pub paths: Vec<String>,
+    /// Branch the index belongs to
+    pub reference: Option<String>,
+    /// Commit the index reflects
+    pub commit: Option<String>,
+    /// The index does not reflect its branch and is not used for queries
+    /// at the branch
+    pub stale: bool,
+    /// Why the last update of the index failed, until it is rebuilt
+    pub error: Option<String>,
+}
+
+/// Which documents of a repository are stored encrypted
+#[derive(Deserialize, Serialize, Debug, Clone, Default, Validate)]
+pub struct StupidEncryptionConfig {
+    #[validate(custom = "validate_encryption_rules")]
+    pub rules: Vec<StupidEncryptionRule>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StupidEncryptionRule {
+    /// A document name, a name prefix followed by * or * for all documents
+    pub documents: String,
+    /// JSON Pointers or JSONPaths of the fields encrypted, the whole
+    /// document when empty
+    #[serde(default)]
+    pub fields: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StupidRotationResult {
+    /// Commit storing the documents again, None when none needed it
+    pub commit: Option<String>,
+    /// Key the documents are encrypted with now
+    pub key: String,
+    /// Documents encrypted again, or decrypted when no rule covers them
+    /// anymore
+    pub documents: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StupidSearchHit {
+    pub name: String,
+    pub id: String,
+    pub score: f64,
+    pub highlights: Vec<StupidHighlight>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StupidHighlight {
+    /// JSON Pointer to the matching string
+    pub path: String,
+    /// Part of the string around the matches as HTML, matches within <em>
+    /// tags
+    pub snippet: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Validate)]
+pub struct StupidWebhook {
+    /// Assigned when the webhook is registered
+    #[serde(default)]
+    pub id: String,
+    /// An http or https URL, certificates are checked against the trust
+    /// store of the system
+    #[validate(url, regex = "crate::stupiddb::regex::WEBHOOK_URL")]
+    pub url: String,
+    /// Key of the HMAC-SHA256 signature sent with every delivery
+    #[validate(length(min = 1))]
+    pub secret: Option<String>,
+    #[validate(length(min = 1))]
+    pub events: Vec<StupidWebhookEvent>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StupidWebhookEvent {
+    Commit,
+    PushSucceeded,
+    PushFailed,
+}
+
+/// Key of the roles a token has on every repository
+pub const ALL_REPOSITORIES: &str = "*";
+/// Separates the repository from the documents in the key of a role
+/// limited to some documents, as in `config.git/payments.*`
+pub const DOCUMENTS_SEPARATOR: char = '/';
+
+#[derive(Deserialize, Serialize, Debug, Clone, Validate)]
+pub struct StupidToken {
+    /// Assigned when the token is created
+    #[serde(default)]
+    pub id: String,
+    /// What the token is used for
+    #[validate(length(min = 1))]
+    pub name: String,
+    /// Roles by repository name, "*" granting a role on every repository.
+    /// A role on `<repository>/<name>` is limited to that document, one on
+    /// `<repository>/<prefix>*` to the documents whose name starts with
+    /// the prefix.
+    #[validate(custom = "validate_roles")]
+    pub roles: HashMap<String, StupidRole>,
+    /// Creation time in seconds since epoch
+    #[serde(default, skip_deserializing)]
+    pub created: u64,
+    /// The bearer token, shown once when it is created and only stored
+    /// hashed
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+/// What a token may do with a repository, each role including the ones
+/// before it
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum StupidRole {
+    Read,
+    Write,
+    Push,
+    Admin,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StupidWebhookPayload {
+    pub delivery: String,
+    pub event: StupidWebhookEvent,
+    pub repository: String,
+    pub data: serde_json::Value,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StupidDelivery {
+    pub id: String,
+    pub webhook: String,
+    pub event: StupidWebhookEvent,
+    pub attempt: u32,
+    /// Seconds since epoch of the attempt
+    pub time: u64,
+    /// HTTP status answered by the webhook, None when it could not be reached
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    pub delivered: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StupidPickaxeQuery {
+    /// JSON Pointer or JSONPath of the value within the documents
+    pub path: String,
+    /// Value looked for, any change of the path when not given
+    pub value: Option<serde_json::Value>,
+    /// Only documents whose name starts with the prefix
+    pub prefix: Option<String>,
+    /// Commits after this revision, exclusive
+    pub from: Option<String>,
+    /// Commits up to this revision, HEAD when not given
+    pub to: Option<String>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StupidPickaxeHit {
+    pub commit: String,
+    pub message: Option<String>,
+    /// Commit time in seconds since epoch
+    pub time: i64,
+    pub document: String,
+    pub kind: StupidPickaxeKind,
+    /// Value before the commit, None when the path did not exist
+    pub old: Option<serde_json::Value>,
+    pub new: Option<serde_json::Value>,
+    pub signature: StupidSignatureStatus,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StupidPickaxeKind {
+    /// The path took the value looked for
+    Appeared,
+    /// The path stopped holding the value looked for
+    Disappeared,
+    /// The value changed, when no value is looked for
+    Changed,
 }
 
 #[derive(Serialize, Deserialize, Validate, Debug, Clone)]
@@ -21,18 +645,172 @@ pub struct StupidBlob {
     pub content: serde_json::Value,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug, Clone, Validate)]
 pub struct StupidConfig {
+    #[validate(length(min = 1))]
     pub root_dir: String,
+    /// Remote credentials by repository name
+    #[serde(default)]
+    pub credentials: HashMap<String, StupidCredentials>,
+    #[serde(default)]
+    #[validate(custom = "validate_server")]
+    pub server: StupidServerConfig,
+    #[serde(default)]
+    #[validate(custom = "validate_limits")]
+    pub limits: StupidLimits,
+    /// Identity of the commits and tags made, the user of the git config
+    /// when not given
+    #[validate(custom = "validate_identity")]
+    pub committer: Option<StupidIdentity>,
+    #[serde(default)]
+    pub push: StupidPushSettings,
+    #[serde(default)]
+    pub auth: StupidAuthSettings,
+    #[serde(default)]
+    pub signing: StupidSigningSettings,
+    #[serde(default)]
+    pub encryption: StupidEncryptionSettings,
+    /// File the configuration was read from, the default file when None
+    #[serde(skip)]
+    pub file: Option<std::path::PathBuf>,
 }
 impl ::std::default::Default for StupidConfig {
     fn default() -> Self {
         Self {
             root_dir: "/var/lib/stupiddb/".into(),
+            credentials: HashMap::new(),
+            server: StupidServerConfig::default(),
+            limits: StupidLimits::default(),
+            committer: None,
+            push: StupidPushSettings::default(),
+            auth: StupidAuthSettings::default(),
+            signing: StupidSigningSettings::default(),
+            encryption: StupidEncryptionSettings::default(),
+            file: None,
         }
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Validate)]
+#[serde(default)]
+pub struct StupidServerConfig {
+    #[validate(length(min = 1))]
+    pub address: String,
+    #[validate(range(min = 1))]
+    pub port: u16,
+    /// Threads serving requests, Rocket's default when not given
+    #[validate(range(min = 1))]
+    pub workers: Option<u16>,
+    pub log_level: StupidLogLevel,
+}
+
+impl Default for StupidServerConfig {
+    fn default() -> Self {
+        StupidServerConfig {
+            address: String::from("localhost"),
+            port: 8000,
+            workers: None,
+            log_level: StupidLogLevel::Normal,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StupidLogLevel {
+    Critical,
+    Normal,
+    Debug,
+    Off,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Validate)]
+#[serde(default)]
+pub struct StupidLimits {
+    /// Bytes of a JSON request body
+    #[validate(range(min = 1))]
+    pub document_size: u64,
+    /// Seconds a watch on a document may last at most
+    #[validate(range(min = 1))]
+    pub watch_timeout: u64,
+    /// Watches on documents waiting at the same time. Each one holds a
+    /// worker while it waits, so this should stay well below the workers.
+    pub watchers: usize,
+    /// Change feeds open at the same time, each holding a worker for as
+    /// long as its client stays connected
+    pub change_feeds: usize,
+    /// Repositories kept open between requests, none when 0
+    pub open_repositories: usize,
+}
+
+impl Default for StupidLimits {
+    fn default() -> Self {
+        StupidLimits {
+            document_size: 1 << 20,
+            watch_timeout: 300,
+            watchers: 4,
+            change_feeds: 4,
+            open_repositories: 64,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct StupidPushSettings {
+    /// Pushes a repository after every commit made to it
+    pub on_commit: bool,
+    /// Pushes to every remote instead of origin only
+    pub all_remotes: bool,
+    /// Pushes all tags along
+    pub tags: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct StupidAuthSettings {
+    /// Requires a bearer token with the role a route asks for, anyone may
+    /// do anything otherwise
+    pub enabled: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct StupidSigningSettings {
+    /// Key the commits are signed with, the private key file for ssh or
+    /// the key id for gpg. Commits are not signed without a key.
+    pub key: Option<String>,
+    pub format: StupidSigningFormat,
+    /// Program signing and verifying, ssh-keygen or gpg when not given
+    pub program: Option<String>,
+    /// File of the ssh keys trusted to sign commits, in the allowed
+    /// signers format of ssh-keygen. Gpg uses its own keyring.
+    pub allowed_signers: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StupidSigningFormat {
+    Ssh,
+    Gpg,
+}
+
+impl Default for StupidSigningFormat {
+    fn default() -> Self {
+        StupidSigningFormat::Ssh
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct StupidEncryptionSettings {
+    /// Keys by id, 32 bytes encoded in base64. Keys no longer encrypted
+    /// with are kept to read the commits made before a rotation.
+    pub keys: HashMap<String, String>,
+    /// Id of the key documents are encrypted with
+    pub key: Option<String>,
+}
+
 pub struct StupidEntry {
     pub entry: git2::IndexEntry,
 }
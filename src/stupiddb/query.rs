@@ -0,0 +1,207 @@
+//! Queries over the JSON documents of a tree.
+//!
+//! Paths are JSON Pointers (`/env/name`) or the dotted subset of JSONPath
+//! (`$.env.name`, `$.hosts[0]`), both resolve to a JSON Pointer.
+
+use super::error::StupidDbError;
+use super::index;
+use super::models::{StupidPredicate, StupidQuery, StupidQueryHit};
+use super::repository::StupidRepository;
+use serde_json::{Map, Value};
+use std::cmp::Ordering;
+
+impl StupidRepository {
+    /// Documents at HEAD or at the given revision matching all predicates
+    /// of the query, ordered by name. Documents not `visible` to the caller
+    /// are left out.
+    pub fn query(
+        &self,
+        query: &StupidQuery,
+        revision: Option<&str>,
+        visible: &dyn Fn(&str) -> bool,
+    ) -> Result<Vec<StupidQueryHit>, StupidDbError> {
+        let commit = match revision {
+            None => self.find_last_commit(),
+            Some(r) => self.find_revision_commit(r),
+        };
+        if commit.is_none() {
+            return match revision {
+                // Nothing was committed yet
+                None => Ok(Vec::new()),
+                Some(_) => Err(StupidDbError::RevisionNotFound),
+            };
+        }
+
+        let commit = commit.unwrap();
+        let tree = commit.tree();
+        if tree.is_err() {
+            return Err(StupidDbError::TreeFetchFailed);
+        }
+
+        // Only the candidates of an index are read, all documents otherwise
+        let tree = tree.unwrap();
+        let entries: Vec<git2::TreeEntry> =
+            match index::candidates(self, commit.id(), &query.filter) {
+                Some(names) => names
+                    .iter()
+                    .filter_map(|n| tree.get_name(n.as_str()))
+                    .collect(),
+                None => tree.iter().collect(),
+            };
+        let limit = query.limit.unwrap_or(usize::MAX);
+        let mut hits = Vec::new();
+        for entry in entries {
+            if hits.len() >= limit {
+                break;
+            }
+
+            let name = entry.name();
+            if name.is_none() || entry.kind() != Some(git2::ObjectType::Blob) {
+                continue;
+            }
+
+            if !visible(name.unwrap()) {
+                continue;
+            }
+
+            let content = self.read_blob_value(entry.id());
+            if content.is_err() {
+                return Err(content.err().unwrap());
+            }
+
+            let content = content.unwrap();
+            if !query.filter.iter().all(|p| matches(&content, p)) {
+                continue;
+            }
+
+            hits.push(StupidQueryHit {
+                name: String::from(name.unwrap()),
+                id: entry.id().to_string(),
+                content: match query.fields.as_ref() {
+                    None => content,
+                    Some(fields) => project(&content, fields),
+                },
+            });
+        }
+
+        Ok(hits)
+    }
+}
+
+/// Resolves a JSON Pointer or JSONPath to a JSON Pointer, None when the
+/// path is neither
+pub fn pointer(path: &str) -> Option<String> {
+    if path.is_empty() || path.starts_with('/') {
+        return Some(String::from(path));
+    }
+
+    if !path.starts_with('$') {
+        return None;
+    }
+
+    let mut pointer = String::new();
+    let mut rest = &path[1..];
+    while !rest.is_empty() {
+        let token = if rest.starts_with('.') {
+            let end = rest[1..]
+                .find(|c| c == '.' || c == '[')
+                .map_or(rest.len(), |i| i + 1);
+            let token = &rest[1..end];
+            rest = &rest[end..];
+            token
+        } else if rest.starts_with("['") {
+            let end = rest.find("']")?;
+            let token = &rest[2..end];
+            rest = &rest[end + 2..];
+            token
+        } else if rest.starts_with('[') {
+            let end = rest.find(']')?;
+            let token = &rest[1..end];
+            token.parse::<usize>().ok()?;
+            rest = &rest[end + 1..];
+            token
+        } else {
+            return None;
+        };
+
+        if token.is_empty() {
+            return None;
+        }
+
+        pointer.push('/');
+        pointer.push_str(token.replace('~', "~0").replace('/', "~1").as_str());
+    }
+
+    Some(pointer)
+}
+
+/// The value at a validated path
+pub fn lookup<'a>(content: &'a Value, path: &str) -> Option<&'a Value> {
+    pointer(path).and_then(|p| content.pointer(p.as_str()))
+}
+
+pub fn matches(content: &Value, predicate: &StupidPredicate) -> bool {
+    matches_value(lookup(content, predicate.path()), predicate)
+}
+
+/// Whether the value found at the path of the predicate matches it
+pub fn matches_value(value: Option<&Value>, predicate: &StupidPredicate) -> bool {
+    match (predicate, value) {
+        (StupidPredicate::Exists { .. }, v) => v.is_some(),
+        (_, None) => false,
+        (
+            StupidPredicate::Eq {
+                value: expected, ..
+            },
+            Some(v),
+        ) => v == expected,
+        (
+            StupidPredicate::Contains {
+                value: expected, ..
+            },
+            Some(v),
+        ) => match (v, expected) {
+            (Value::Array(items), _) => items.contains(expected),
+            (Value::String(s), Value::String(e)) => s.contains(e.as_str()),
+            _ => false,
+        },
+        (
+            StupidPredicate::Range {
+                gt, gte, lt, lte, ..
+            },
+            Some(v),
+        ) => {
+            let within = |bound: &Option<Value>, accepted: &[Ordering]| {
+                bound.as_ref().map_or(true, |b| {
+                    compare(v, b).map_or(false, |o| accepted.contains(&o))
+                })
+            };
+
+            within(gt, &[Ordering::Greater])
+                && within(gte, &[Ordering::Greater, Ordering::Equal])
+                && within(lt, &[Ordering::Less])
+                && within(lte, &[Ordering::Less, Ordering::Equal])
+        }
+    }
+}
+
+/// Orders numbers and strings, other values and mixed types do not compare
+pub fn compare(value: &Value, other: &Value) -> Option<Ordering> {
+    match (value, other) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+/// The selected fields of a document by their path, missing ones left out
+fn project(content: &Value, fields: &Vec<String>) -> Value {
+    let mut projection = Map::new();
+    for field in fields.iter() {
+        if let Some(value) = lookup(content, field.as_str()) {
+            projection.insert(field.to_string(), value.clone());
+        }
+    }
+
+    Value::Object(projection)
+}
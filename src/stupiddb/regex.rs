@@ -4,6 +4,15 @@ use regex::Regex;
 lazy_static! {
     pub static ref NAME: Regex = Regex::new(r"^[a-zA-Z0-9_-]+\.git$").unwrap();
     pub static ref BLOB_NAME: Regex = Regex::new(r"^(.?[a-zA-Z0-9_-]+)+$").unwrap();
-    pub static ref ORIGIN: Regex =
-        Regex::new(r"((git|ssh|http(s)?)|(git@[\w\.]+))(:(//)?)([\w\.@:/~-]+)(\.git)(/)?").unwrap();
+    pub static ref REF_NAME: Regex = Regex::new(r"^[a-zA-Z0-9_-]+(\.[a-zA-Z0-9_-]+)*$").unwrap();
+    /// A document name, or a name prefix followed by *
+    pub static ref DOCUMENTS: Regex = Regex::new(r"^([a-zA-Z0-9_.-]+\*?|\*)$").unwrap();
+    pub static ref REMOTE_NAME: Regex = Regex::new(r"^[a-zA-Z0-9_-]+$").unwrap();
+    pub static ref REFSPEC: Regex = Regex::new(r"^\+?refs/[\w./-]+(:refs/[\w./-]+)?$").unwrap();
+    pub static ref WEBHOOK_URL: Regex = Regex::new(r"^(?i)https?://").unwrap();
+    /// A remote over the network ending in .git, or a local one
+    pub static ref ORIGIN: Regex = Regex::new(
+        r"^((git|ssh|https?)://[\w.@:/~-]+\.git/?|git@[\w.]+:[\w.@/~-]+\.git/?|file://[\w.@/~-]+)$"
+    )
+    .unwrap();
 }
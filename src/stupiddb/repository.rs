@@ -1,13 +1,42 @@
+use super::credentials;
+use super::encryption;
 use super::error::StupidDbError;
+use super::events;
+use super::index;
+use super::info;
+use super::merge;
+use super::mirror;
 
 use super::models;
+use super::search;
+use super::signing;
+use super::webhook;
 use git2::Repository;
+use std::cell::Cell;
+use std::collections::HashMap;
 use std::path::Path;
-static DEFAULT_GIT_SSH_USERNAME: &str = "git";
+
+pub const DEFAULT_REMOTE: &str = "origin";
+const MIRROR_KEY: &str = "stupiddb.mirror";
+const MIRROR_INTERVAL_KEY: &str = "stupiddb.mirrorInterval";
+const MIRROR_REMOTE_KEY: &str = "stupiddb.mirrorRemote";
+const DEFAULT_MIRROR_INTERVAL: u64 = 300;
 
 pub struct StupidRepository {
     path: String,
     pub origin: Option<String>,
+    pub remotes: Vec<models::StupidRemote>,
+    pub credentials: Option<models::StupidCredentials>,
+    pub mirror: Option<models::StupidMirror>,
+    /// Identity of the commits and tags made, the user of the git config
+    /// when None
+    pub committer: Option<models::StupidIdentity>,
+    /// Key the commits made are signed with, and how signatures are checked
+    pub signing: models::StupidSigningSettings,
+    /// Keys documents are encrypted with
+    pub encryption: models::StupidEncryptionSettings,
+    /// Remotes or mirror settings changed since the repository was opened
+    stale: Cell<bool>,
     _repo: Option<Repository>,
 }
 
@@ -17,6 +46,21 @@ impl StupidRepository {
             path,
             _repo: None,
             origin: None,
+            remotes: Vec::new(),
+            credentials: None,
+            mirror: None,
+            committer: None,
+            signing: models::StupidSigningSettings::default(),
+            encryption: models::StupidEncryptionSettings::default(),
+            stale: Cell::new(false),
+        }
+    }
+
+    /// Signature of the commits and tags made by stupiddb
+    fn signature(&self, repo: &Repository) -> Result<git2::Signature<'static>, git2::Error> {
+        match self.committer.as_ref() {
+            Some(c) => git2::Signature::now(c.name.as_str(), c.email.as_str()),
+            None => repo.signature(),
         }
     }
 
@@ -24,6 +68,16 @@ impl StupidRepository {
         &self,
         tree_oid: git2::Oid,
         blob: &models::StupidBlob,
+    ) -> Result<git2::Oid, StupidDbError> {
+        let message = blob.message.as_ref().map_or("no message", |m| m.as_str());
+        self.commit_head(tree_oid, message)
+    }
+
+    /// Commits a tree written from the index onto HEAD
+    pub(super) fn commit_head(
+        &self,
+        tree_oid: git2::Oid,
+        message: &str,
     ) -> Result<git2::Oid, StupidDbError> {
         if !self._repo.is_some() {
             return Err(StupidDbError::RepoNotInitialized);
@@ -34,12 +88,6 @@ impl StupidRepository {
             return Err(index_result.err().unwrap());
         }
 
-        let repo = self._repo.as_ref().unwrap();
-        let signature = repo.signature();
-        if signature.is_err() {
-            return Err(StupidDbError::SignatureFailed);
-        }
-
         let mut parent_commit = self.find_last_commit();
         if parent_commit.is_none() {
             let result = self.create_initial_commit();
@@ -58,20 +106,992 @@ impl StupidRepository {
             return Err(StupidDbError::TreeFetchFailed);
         }
 
-        let message = blob.message.as_ref().map_or("no message", |m| m.as_str());
+        self.create_commit(
+            "HEAD",
+            message,
+            tree.as_ref().unwrap(),
+            &[parent_commit.as_ref().unwrap()],
+        )
+    }
+
+    /// Restores a document to its version at a revision as new commit on
+    /// HEAD, removing it when it did not exist at that revision
+    pub fn revert_document(
+        &self,
+        name: &str,
+        revision: &str,
+        message: Option<&str>,
+    ) -> Result<git2::Oid, StupidDbError> {
+        if !self._repo.is_some() {
+            return Err(StupidDbError::RepoNotInitialized);
+        }
+
+        let commit = self.find_revision_commit(revision);
+        if commit.is_none() {
+            return Err(StupidDbError::RevisionNotFound);
+        }
+
+        let tree = commit.as_ref().unwrap().tree();
+        if tree.is_err() {
+            return Err(StupidDbError::TreeFetchFailed);
+        }
+
+        let index_result = self.index();
+        if index_result.is_err() {
+            return Err(index_result.err().unwrap());
+        }
+
+        let mut index = index_result.unwrap();
+        let entry = tree.as_ref().unwrap().get_name(name).map(|e| e.id());
+        let update_result = match entry {
+            Some(oid) => {
+                let entry = models::StupidEntry::new(oid, String::from(name).into_bytes());
+                index.add(&entry.entry)
+            }
+            None => {
+                if index.get_path(Path::new(name), 0).is_none() {
+                    return Err(StupidDbError::DocumentNotFound);
+                }
+                index.remove_path(Path::new(name))
+            }
+        };
+
+        let tree_result = update_result
+            .and_then(|_| index.write())
+            .and_then(|_| index.write_tree());
+        if tree_result.is_err() {
+            return Err(StupidDbError::WriteTreeFailed);
+        }
+
+        let default_message = format!("Revert {} to {}", name, revision);
+        self.commit_head(
+            tree_result.unwrap(),
+            message.unwrap_or(default_message.as_str()),
+        )
+    }
+
+    /// Creates a commit on HEAD undoing the changes of the given commit.
+    ///
+    /// The changes are undone with a three-way merge, so documents changed
+    /// since keep those changes unless they touch the same fields.
+    pub fn revert_commit(
+        &self,
+        revision: &str,
+        message: Option<&str>,
+    ) -> Result<models::StupidRevertResult, StupidDbError> {
+        if !self._repo.is_some() {
+            return Err(StupidDbError::RepoNotInitialized);
+        }
+
+        let repo = self._repo.as_ref().unwrap();
+        let commit = self.find_revision_commit(revision);
+        if commit.is_none() {
+            return Err(StupidDbError::RevisionNotFound);
+        }
+
+        let head = self.find_last_commit();
+        if head.is_none() {
+            return Err(StupidDbError::CommitFetchFailed);
+        }
+
+        let commit = commit.unwrap();
+        // Merge commits are reverted relative to their first parent
+        let parent_tree = match commit.parent(0) {
+            Ok(p) => p.tree(),
+            Err(_) => repo
+                .treebuilder(None)
+                .and_then(|b| b.write())
+                .and_then(|t| repo.find_tree(t)),
+        };
+
+        let trees = (commit.tree(), head.unwrap().tree(), parent_tree);
+        if trees.0.is_err() || trees.1.is_err() || trees.2.is_err() {
+            return Err(StupidDbError::TreeFetchFailed);
+        }
+
+        let merge_result = self.merge_trees(
+            Some(&trees.0.unwrap()),
+            &trees.1.unwrap(),
+            &trees.2.unwrap(),
+            &merge::MergeOptions::default(),
+        );
+        if merge_result.is_err() {
+            return Err(merge_result.err().unwrap());
+        }
+
+        let (tree_oid, conflicts) = merge_result.unwrap();
+        if !conflicts.is_empty() {
+            return Ok(models::StupidRevertResult {
+                commit: None,
+                conflicts,
+            });
+        }
+
+        let tree = self.find_tree(tree_oid.unwrap());
+        if tree.is_none() {
+            return Err(StupidDbError::TreeFetchFailed);
+        }
+
+        let index_result = self.index();
+        if index_result.is_err() {
+            return Err(index_result.err().unwrap());
+        }
+
+        let mut index = index_result.unwrap();
+        if index
+            .read_tree(tree.as_ref().unwrap())
+            .and_then(|_| index.write())
+            .is_err()
+        {
+            return Err(StupidDbError::IndexFetchFailed);
+        }
+
+        let default_message = format!(
+            "Revert \"{}\"\n\nThis reverts commit {}.",
+            commit.summary().unwrap_or_default(),
+            commit.id()
+        );
+        self.commit_head(
+            tree_oid.unwrap(),
+            message.unwrap_or(default_message.as_str()),
+        )
+        .map(|oid| models::StupidRevertResult {
+            commit: Some(oid.to_string()),
+            conflicts: Vec::new(),
+        })
+    }
+
+    /// Commits a blob onto a branch other than the one HEAD points at.
+    ///
+    /// The index belongs to HEAD, so the tree is built in an in-memory
+    /// index read from the tip of the branch.
+    pub fn commit_to_branch(
+        &self,
+        branch: &str,
+        blob: &models::StupidBlob,
+    ) -> Result<git2::Oid, StupidDbError> {
+        if !self._repo.is_some() {
+            return Err(StupidDbError::RepoNotInitialized);
+        }
+
+        let reference = branch_ref(branch);
+        let tip = self.find_branch_commit(branch);
+        if tip.is_none() {
+            return Err(StupidDbError::BranchNotFound);
+        }
+
+        let tip = tip.unwrap();
+        let tip_tree = tip.tree();
+        if tip_tree.is_err() {
+            return Err(StupidDbError::TreeFetchFailed);
+        }
+
+        let index = git2::Index::new();
+        if index.is_err() {
+            return Err(StupidDbError::IndexFetchFailed);
+        }
+
+        let mut index = index.unwrap();
+        let entry = models::StupidEntry::new(blob.oid.unwrap(), blob.name.clone().into_bytes());
+        let build_result = index
+            .read_tree(tip_tree.as_ref().unwrap())
+            .and_then(|_| index.add(&entry.entry))
+            .and_then(|_| index.write_tree_to(self._repo.as_ref().unwrap()));
+        if build_result.is_err() {
+            return Err(StupidDbError::WriteTreeFailed);
+        }
+
+        let tree = self.find_tree(build_result.unwrap());
+        if tree.is_none() {
+            return Err(StupidDbError::TreeFetchFailed);
+        }
+
+        let message = blob.message.as_ref().map_or("no message", |m| m.as_str());
+        self.create_commit(reference.as_str(), message, tree.as_ref().unwrap(), &[&tip])
+    }
+
+    /// Creates a commit and moves `update_ref` to it.
+    ///
+    /// Every commit stupiddb makes goes through here.
+    fn create_commit(
+        &self,
+        update_ref: &str,
+        message: &str,
+        tree: &git2::Tree,
+        parents: &[&git2::Commit],
+    ) -> Result<git2::Oid, StupidDbError> {
+        if !self._repo.is_some() {
+            return Err(StupidDbError::RepoNotInitialized);
+        }
+
+        let repo = self._repo.as_ref().unwrap();
+        let signature = self.signature(repo);
+        if signature.is_err() {
+            return Err(StupidDbError::SignatureFailed);
+        }
+
+        let commit_result = match self.signing.key.as_ref() {
+            Some(key) => self.commit_signed(
+                repo,
+                key.as_str(),
+                signature.as_ref().unwrap(),
+                message,
+                tree,
+                parents,
+            ),
+            None => repo
+                .commit(
+                    None,
+                    signature.as_ref().unwrap(),
+                    signature.as_ref().unwrap(),
+                    message,
+                    tree,
+                    parents,
+                )
+                .map_err(|_| StupidDbError::CommitFailed),
+        };
+        if commit_result.is_err() {
+            return Err(commit_result.err().unwrap());
+        }
+
+        // HEAD moves the branch it points to, unless it is detached. The
+        // ref has to be at the first parent still, as git2 would check.
+        let oid = commit_result.unwrap();
+        let reference = match update_ref {
+            "HEAD" => self.head_branch(),
+            r => Some(String::from(r)),
+        };
+        let name = reference.clone().unwrap_or(String::from("HEAD"));
+        let log_message = format!("commit: {}", message.lines().next().unwrap_or_default());
+        let updated = self.update_ref(
+            name.as_str(),
+            Some(oid),
+            parents.first().map(|p| p.id()),
+            false,
+            log_message.as_str(),
+            StupidDbError::CommitFailed,
+        );
+        if updated.is_err() {
+            // Changes staged for a refused commit on HEAD must not linger in
+            // the working tree until the mirror syncs again
+            let head = self.find_last_commit();
+            if update_ref == "HEAD" && head.is_some() {
+                let _ = repo.reset(head.unwrap().as_object(), git2::ResetType::Hard, None);
+            }
+            return Err(updated.err().unwrap());
+        }
+
+        let event = repo
+            .find_commit(oid)
+            .map_err(|_| StupidDbError::CommitFetchFailed)
+            .and_then(|c| self.change_event(&c, reference.as_ref().map(|r| r.as_str())));
+        if event.is_ok() {
+            events::publish(event.unwrap());
+        }
+
+        self.update_indexes(name.as_str());
+
+        Ok(oid)
+    }
+
+    /// Creates a commit signed with the key of the server
+    fn commit_signed(
+        &self,
+        repo: &Repository,
+        key: &str,
+        signature: &git2::Signature,
+        message: &str,
+        tree: &git2::Tree,
+        parents: &[&git2::Commit],
+    ) -> Result<git2::Oid, StupidDbError> {
+        let buffer = repo.commit_create_buffer(signature, signature, message, tree, parents);
+        if buffer.is_err() {
+            return Err(StupidDbError::CommitFailed);
+        }
+
+        let buffer = buffer.unwrap();
+        let content = buffer.as_str();
+        if content.is_none() {
+            return Err(StupidDbError::CommitFailed);
+        }
+
+        let signed = signing::sign(&self.signing, key, content.unwrap());
+        if signed.is_err() {
+            return Err(signed.err().unwrap());
+        }
+
+        repo.commit_signed(content.unwrap(), signed.unwrap().as_str(), None)
+            .map_err(|_| StupidDbError::CommitFailed)
+    }
+
+    /// Creates, moves or deletes a ref, deleting it along with its branch
+    /// config when there is no target. With `current` the ref has to point
+    /// at that commit still, without it an existing ref is only replaced
+    /// when forced.
+    ///
+    /// Every ref stupiddb changes goes through here, but for the reset of a
+    /// mirror to its remote: the refs of a mirror only follow its remote.
+    fn update_ref(
+        &self,
+        name: &str,
+        target: Option<git2::Oid>,
+        current: Option<git2::Oid>,
+        force: bool,
+        log_message: &str,
+        failure: StupidDbError,
+    ) -> Result<(), StupidDbError> {
+        if !self._repo.is_some() {
+            return Err(StupidDbError::RepoNotInitialized);
+        }
+
+        if self.mirror.is_some() {
+            return Err(StupidDbError::RepoIsMirror);
+        }
+
+        let repo = self._repo.as_ref().unwrap();
+        let updated = match (target, current) {
+            (None, _) => repo
+                .find_reference(name)
+                .and_then(|r| git2::Branch::wrap(r).delete()),
+            (Some(oid), Some(current)) => repo
+                .reference_matching(name, oid, true, current, log_message)
+                .map(|_| ()),
+            (Some(oid), None) => repo.reference(name, oid, force, log_message).map(|_| ()),
+        };
+
+        updated.map_err(|_| failure)
+    }
+
+    /// Removes a document from the index and commits the result onto HEAD
+    pub fn remove(&self, name: &str, message: &str) -> Result<git2::Oid, StupidDbError> {
+        let index_result = self.index();
+        if index_result.is_err() {
+            return Err(index_result.err().unwrap());
+        }
+
+        let mut index = index_result.unwrap();
+        if index.get_path(Path::new(name), 0).is_none() {
+            return Err(StupidDbError::DocumentNotFound);
+        }
+
+        let remove_result = index
+            .remove_path(Path::new(name))
+            .and_then(|_| index.write())
+            .and_then(|_| index.write_tree());
+        if remove_result.is_err() {
+            return Err(StupidDbError::WriteTreeFailed);
+        }
+
+        self.commit_head(remove_result.unwrap(), message)
+    }
+
+    /// Names of the documents at HEAD or at the given revision
+    pub fn documents(&self, revision: Option<&str>) -> Result<Vec<String>, StupidDbError> {
+        if !self._repo.is_some() {
+            return Err(StupidDbError::RepoNotInitialized);
+        }
+
+        let commit = match revision {
+            None => self.find_last_commit(),
+            Some(r) => self.find_revision_commit(r),
+        };
+        if commit.is_none() {
+            return match revision {
+                None => Ok(Vec::new()),
+                Some(_) => Err(StupidDbError::RevisionNotFound),
+            };
+        }
+
+        let tree = commit.unwrap().tree();
+        if tree.is_err() {
+            return Err(StupidDbError::TreeFetchFailed);
+        }
+
+        Ok(tree
+            .unwrap()
+            .iter()
+            .filter_map(|e| e.name().map(String::from))
+            .collect())
+    }
+
+    /// Reads a document at HEAD or at the given revision
+    pub fn read(
+        &self,
+        name: &str,
+        revision: Option<&str>,
+    ) -> Result<models::StupidBlob, StupidDbError> {
+        if !self._repo.is_some() {
+            return Err(StupidDbError::RepoNotInitialized);
+        }
+
+        let commit = match revision {
+            None => self.find_last_commit(),
+            Some(r) => self.find_revision_commit(r),
+        };
+        if commit.is_none() {
+            return Err(StupidDbError::CommitFetchFailed);
+        }
+
+        let tree = commit.unwrap().tree();
+        if tree.is_err() {
+            return Err(StupidDbError::TreeFetchFailed);
+        }
+
+        let entry = tree.as_ref().unwrap().get_name(name).map(|e| e.id());
+        if entry.is_none() {
+            return Err(StupidDbError::DocumentNotFound);
+        }
+
+        let oid = entry.unwrap();
+        self.read_blob_value(oid).map(|content| models::StupidBlob {
+            id: Some(oid.to_string()),
+            oid: Some(oid),
+            message: None,
+            name: String::from(name),
+            content,
+        })
+    }
+
+    /// Parses the JSON content of a blob, decrypting what is encrypted
+    pub fn read_blob_value(&self, oid: git2::Oid) -> Result<serde_json::Value, StupidDbError> {
+        self.read_stored_value(oid)
+            .and_then(|stored| encryption::unseal(&self.encryption, stored))
+    }
+
+    /// Parses the JSON content of a blob as it is stored, encrypted values
+    /// left sealed
+    pub fn read_stored_value(&self, oid: git2::Oid) -> Result<serde_json::Value, StupidDbError> {
+        if !self._repo.is_some() {
+            return Err(StupidDbError::RepoNotInitialized);
+        }
+
+        let blob = self._repo.as_ref().unwrap().find_blob(oid);
+        if blob.is_err() {
+            return Err(StupidDbError::BlobFailed);
+        }
+
+        serde_json::from_slice(blob.unwrap().content()).map_err(|_| StupidDbError::BlobFailed)
+    }
+
+    /// Serializes a document the way hash-object stores it, encrypting it
+    /// as the rules of the repository say
+    pub fn write_blob_value(
+        &self,
+        name: &str,
+        value: &serde_json::Value,
+    ) -> Result<git2::Oid, StupidDbError> {
+        let sealed = encryption::seal(self, name, value);
+        if sealed.is_err() {
+            return Err(sealed.err().unwrap());
+        }
+
+        self.blob(sealed.unwrap().to_string().as_bytes())
+    }
+
+    /// Merges the source branch into the target branch.
+    ///
+    /// Documents changed on both sides are merged field by field, nothing
+    /// is committed when a field was changed differently on both sides.
+    pub fn merge_branches(
+        &self,
+        source: &str,
+        target: &str,
+        message: Option<&str>,
+        options: &merge::MergeOptions,
+    ) -> Result<models::StupidMergeResult, StupidDbError> {
+        if !self._repo.is_some() {
+            return Err(StupidDbError::RepoNotInitialized);
+        }
+
+        let repo = self._repo.as_ref().unwrap();
+        let theirs = self.find_branch_commit(source);
+        let ours = self.find_branch_commit(target);
+        if theirs.is_none() || ours.is_none() {
+            return Err(StupidDbError::BranchNotFound);
+        }
+
+        let theirs = theirs.unwrap();
+        let ours = ours.unwrap();
+        let reference = branch_ref(target);
+
+        if theirs.id() == ours.id()
+            || repo
+                .graph_descendant_of(ours.id(), theirs.id())
+                .unwrap_or(false)
+        {
+            return Ok(models::StupidMergeResult {
+                status: models::StupidMergeStatus::UpToDate,
+                commit: Some(ours.id().to_string()),
+                conflicts: Vec::new(),
+            });
+        }
+
+        if repo
+            .graph_descendant_of(theirs.id(), ours.id())
+            .unwrap_or(false)
+        {
+            let update_result = self.update_ref(
+                reference.as_str(),
+                Some(theirs.id()),
+                Some(ours.id()),
+                true,
+                "merge: fast-forward",
+                StupidDbError::CommitFailed,
+            );
+            if update_result.is_err() {
+                return Err(update_result.err().unwrap());
+            }
+
+            let index_result = self.reset_index_if_head(target, &theirs);
+            if index_result.is_err() {
+                return Err(index_result.err().unwrap());
+            }
+
+            self.ref_moved(reference.as_str(), Some(ours.id().to_string().as_str()));
+
+            return Ok(models::StupidMergeResult {
+                status: models::StupidMergeStatus::FastForward,
+                commit: Some(theirs.id().to_string()),
+                conflicts: Vec::new(),
+            });
+        }
+
+        let base = repo
+            .merge_base(ours.id(), theirs.id())
+            .and_then(|b| repo.find_commit(b))
+            .and_then(|c| c.tree())
+            .ok();
+
+        let trees = (ours.tree(), theirs.tree());
+        if trees.0.is_err() || trees.1.is_err() {
+            return Err(StupidDbError::TreeFetchFailed);
+        }
+
+        let merge_result =
+            self.merge_trees(base.as_ref(), &trees.0.unwrap(), &trees.1.unwrap(), options);
+        if merge_result.is_err() {
+            return Err(merge_result.err().unwrap());
+        }
+
+        let (tree_oid, conflicts) = merge_result.unwrap();
+        if !conflicts.is_empty() {
+            return Ok(models::StupidMergeResult {
+                status: models::StupidMergeStatus::Conflict,
+                commit: None,
+                conflicts,
+            });
+        }
+
+        let tree = self.find_tree(tree_oid.unwrap());
+        if tree.is_none() {
+            return Err(StupidDbError::TreeFetchFailed);
+        }
+
+        let default_message = format!("Merge branch '{}' into {}", source, target);
+        let commit_result = self.create_commit(
+            reference.as_str(),
+            message.unwrap_or(default_message.as_str()),
+            tree.as_ref().unwrap(),
+            &[&ours, &theirs],
+        );
+        if commit_result.is_err() {
+            return Err(commit_result.err().unwrap());
+        }
+
+        let commit = repo.find_commit(commit_result.unwrap());
+        if commit.is_err() {
+            return Err(StupidDbError::CommitFetchFailed);
+        }
+
+        let index_result = self.reset_index_if_head(target, commit.as_ref().unwrap());
+        if index_result.is_err() {
+            return Err(index_result.err().unwrap());
+        }
+
+        Ok(models::StupidMergeResult {
+            status: models::StupidMergeStatus::Merged,
+            commit: Some(commit.unwrap().id().to_string()),
+            conflicts: Vec::new(),
+        })
+    }
+
+    /// Merges document trees, returning the merged tree or the conflicts
+    pub fn merge_trees(
+        &self,
+        base: Option<&git2::Tree>,
+        ours: &git2::Tree,
+        theirs: &git2::Tree,
+        options: &merge::MergeOptions,
+    ) -> Result<(Option<git2::Oid>, Vec<models::StupidConflict>), StupidDbError> {
+        let repo = self._repo.as_ref().unwrap();
+        let entry_id = |tree: Option<&git2::Tree>, name: &str| {
+            tree.and_then(|t| t.get_name(name).map(|e| e.id()))
+        };
+
+        let mut names: Vec<String> = base
+            .into_iter()
+            .chain(vec![ours, theirs])
+            .flat_map(|t| t.iter().filter_map(|e| e.name().map(String::from)))
+            .collect();
+        names.sort();
+        names.dedup();
+
+        let index = git2::Index::new();
+        if index.is_err() {
+            return Err(StupidDbError::IndexFetchFailed);
+        }
+
+        let mut index = index.unwrap();
+        let mut conflicts = Vec::new();
+        for name in names.iter() {
+            let b = entry_id(base, name);
+            let o = entry_id(Some(ours), name);
+            let t = entry_id(Some(theirs), name);
+
+            let merged = if o == t || b == t {
+                o
+            } else if b == o {
+                t
+            } else {
+                let values = (
+                    self.read_optional(b),
+                    self.read_optional(o),
+                    self.read_optional(t),
+                );
+                if values.0.is_err() || values.1.is_err() || values.2.is_err() {
+                    return Err(StupidDbError::BlobFailed);
+                }
+
+                let (bv, ov, tv) = (values.0.unwrap(), values.1.unwrap(), values.2.unwrap());
+                match merge::merge(bv.as_ref(), ov.as_ref(), tv.as_ref(), options) {
+                    Err(c) => {
+                        conflicts.extend(c.into_iter().map(|c| models::StupidConflict {
+                            document: name.to_string(),
+                            kind: c.kind,
+                            path: c.path,
+                            base: c.base,
+                            ours: c.ours,
+                            theirs: c.theirs,
+                        }));
+                        None
+                    }
+                    Ok(None) => None,
+                    Ok(Some(value)) => {
+                        let write_result = self.write_blob_value(name, &value);
+                        if write_result.is_err() {
+                            return Err(write_result.err().unwrap());
+                        }
+                        Some(write_result.unwrap())
+                    }
+                }
+            };
+
+            if merged.is_some() {
+                let entry = models::StupidEntry::new(merged.unwrap(), name.clone().into_bytes());
+                if index.add(&entry.entry).is_err() {
+                    return Err(StupidDbError::IndexFetchFailed);
+                }
+            }
+        }
+
+        if !conflicts.is_empty() {
+            return Ok((None, conflicts));
+        }
+
+        index
+            .write_tree_to(repo)
+            .map(|t| (Some(t), conflicts))
+            .map_err(|_| StupidDbError::WriteTreeFailed)
+    }
+
+    fn read_optional(
+        &self,
+        oid: Option<git2::Oid>,
+    ) -> Result<Option<serde_json::Value>, StupidDbError> {
+        match oid {
+            None => Ok(None),
+            Some(o) => self.read_blob_value(o).map(|v| Some(v)),
+        }
+    }
+
+    /// Keeps the index in line with HEAD after its branch moved
+    fn reset_index_if_head(
+        &self,
+        branch: &str,
+        commit: &git2::Commit,
+    ) -> Result<(), StupidDbError> {
+        if !self.is_head_branch(branch) {
+            return Ok(());
+        }
+
+        let tree = commit.tree();
+        if tree.is_err() {
+            return Err(StupidDbError::TreeFetchFailed);
+        }
+
+        let index_result = self.index();
+        if index_result.is_err() {
+            return Err(index_result.err().unwrap());
+        }
+
+        let mut index = index_result.unwrap();
+        index
+            .read_tree(tree.as_ref().unwrap())
+            .and_then(|_| index.write())
+            .map_err(|_| StupidDbError::IndexFetchFailed)
+    }
+
+    pub fn tags(&self) -> Result<Vec<models::StupidTag>, StupidDbError> {
+        if !self._repo.is_some() {
+            return Err(StupidDbError::RepoNotInitialized);
+        }
+
+        let repo = self._repo.as_ref().unwrap();
+        let names = repo.tag_names(None);
+        if names.is_err() {
+            return Err(StupidDbError::TagFetchFailed);
+        }
+
+        let tags = names
+            .unwrap()
+            .iter()
+            .flatten()
+            .filter_map(|name| {
+                let reference = repo
+                    .find_reference(format!("refs/tags/{}", name).as_str())
+                    .ok()?;
+                // Peeling would go through an annotated tag to its commit
+                let object = match reference.target() {
+                    Some(oid) => repo.find_object(oid, None).ok()?,
+                    None => reference.peel(git2::ObjectType::Any).ok()?,
+                };
+                let target = object.peel_to_commit().ok().map(|c| c.id().to_string());
+                // Lightweight tags made outside stupiddb have no message or tagger
+                let tag = object.as_tag();
+                Some(models::StupidTag {
+                    name: String::from(name),
+                    target,
+                    message: tag.and_then(|t| t.message()).map(String::from),
+                    tagger: tag
+                        .and_then(|t| t.tagger())
+                        .map(|s| models::StupidIdentity {
+                            name: String::from(s.name().unwrap_or_default()),
+                            email: String::from(s.email().unwrap_or_default()),
+                        }),
+                    time: tag.and_then(|t| t.tagger()).map(|s| s.when().seconds()),
+                })
+            })
+            .collect();
+
+        Ok(tags)
+    }
+
+    /// Creates an annotated tag, replacing an existing one only when forced
+    pub fn create_tag(
+        &self,
+        request: &models::StupidTagRequest,
+    ) -> Result<models::StupidTag, StupidDbError> {
+        if !self._repo.is_some() {
+            return Err(StupidDbError::RepoNotInitialized);
+        }
+
+        let repo = self._repo.as_ref().unwrap();
+        let reference = format!("refs/tags/{}", request.name);
+        if !request.force && repo.find_reference(reference.as_str()).is_ok() {
+            return Err(StupidDbError::TagExists);
+        }
+
+        let commit = match request.target.as_ref() {
+            None => self.find_last_commit(),
+            Some(r) => self.find_revision_commit(r.as_str()),
+        };
+        if commit.is_none() {
+            return Err(StupidDbError::CommitFetchFailed);
+        }
+
+        let tagger = match request.tagger.as_ref() {
+            None => self.signature(repo),
+            Some(t) => git2::Signature::now(t.name.as_str(), t.email.as_str()),
+        };
+        if tagger.is_err() {
+            return Err(StupidDbError::SignatureFailed);
+        }
+
+        // The tag object is written as git2 would, its ref like any other
+        let commit = commit.unwrap();
+        let tagger = tagger.unwrap();
+        let tag_result = repo
+            .odb()
+            .and_then(|odb| {
+                let buffer = tag_buffer(&commit, request.name.as_str(), &tagger, &request.message);
+                odb.write(git2::ObjectType::Tag, buffer.as_bytes())
+            })
+            .map_err(|_| StupidDbError::TagFailed)
+            .and_then(|oid| {
+                self.update_ref(
+                    reference.as_str(),
+                    Some(oid),
+                    None,
+                    request.force,
+                    format!("tag: {}", request.name).as_str(),
+                    StupidDbError::TagFailed,
+                )
+            });
+
+        match tag_result {
+            Err(e) => Err(e),
+            Ok(_) => Ok(models::StupidTag {
+                name: request.name.to_string(),
+                target: Some(commit.id().to_string()),
+                message: Some(request.message.to_string()),
+                tagger: Some(models::StupidIdentity {
+                    name: String::from(tagger.name().unwrap_or_default()),
+                    email: String::from(tagger.email().unwrap_or_default()),
+                }),
+                time: Some(tagger.when().seconds()),
+            }),
+        }
+    }
+
+    pub fn branches(&self) -> Result<Vec<models::StupidBranch>, StupidDbError> {
+        if !self._repo.is_some() {
+            return Err(StupidDbError::RepoNotInitialized);
+        }
+
+        let branches = self
+            ._repo
+            .as_ref()
+            .unwrap()
+            .branches(Some(git2::BranchType::Local));
+        if branches.is_err() {
+            return Err(StupidDbError::BranchFailed);
+        }
+
+        let head = self.head_branch();
+        let result = branches
+            .unwrap()
+            .filter_map(|b| b.ok())
+            .filter_map(|(branch, _)| {
+                let name = branch.name().ok().flatten().map(String::from);
+                name.map(|n| models::StupidBranch {
+                    current: head
+                        .as_ref()
+                        .map_or(false, |h| *h == branch_ref(n.as_str())),
+                    head: branch.get().target().map(|t| t.to_string()),
+                    name: n,
+                })
+            })
+            .collect();
+
+        Ok(result)
+    }
+
+    /// Creates a branch at the given revision, HEAD when none is given
+    pub fn create_branch(
+        &self,
+        name: &str,
+        from: Option<&str>,
+    ) -> Result<models::StupidBranch, StupidDbError> {
+        if !self._repo.is_some() {
+            return Err(StupidDbError::RepoNotInitialized);
+        }
+
+        if self.find_branch_commit(name).is_some() {
+            return Err(StupidDbError::BranchExists);
+        }
+
+        if from.is_none() && self.find_last_commit().is_none() {
+            let result = self.create_initial_commit();
+            if result.is_err() {
+                return Err(result.err().unwrap());
+            }
+        }
+
+        let commit = match from {
+            None => self.find_last_commit(),
+            Some(r) => self.find_revision_commit(r),
+        };
+        if commit.is_none() {
+            return Err(StupidDbError::CommitFetchFailed);
+        }
+
+        let commit = commit.unwrap();
+        self.update_ref(
+            branch_ref(name).as_str(),
+            Some(commit.id()),
+            None,
+            false,
+            format!("branch: Created from {}", from.unwrap_or("HEAD")).as_str(),
+            StupidDbError::BranchFailed,
+        )
+        .map(|_| models::StupidBranch {
+            name: String::from(name),
+            head: Some(commit.id().to_string()),
+            current: false,
+        })
+    }
+
+    pub fn delete_branch(&self, name: &str) -> Result<(), StupidDbError> {
+        if !self._repo.is_some() {
+            return Err(StupidDbError::RepoNotInitialized);
+        }
+
+        if self.is_head_branch(name) {
+            return Err(StupidDbError::BranchIsHead);
+        }
+
+        if self.find_branch_commit(name).is_none() {
+            return Err(StupidDbError::BranchNotFound);
+        }
+
+        let deleted = self.update_ref(
+            branch_ref(name).as_str(),
+            None,
+            None,
+            false,
+            "",
+            StupidDbError::BranchFailed,
+        );
+        if deleted.is_err() {
+            return deleted;
+        }
+
+        // The index of a deleted branch would only take up space
+        let _ = index::remove(self, branch_ref(name).as_str());
+        Ok(())
+    }
+
+    pub fn is_head_branch(&self, name: &str) -> bool {
+        self.head_branch().map_or(false, |h| h == branch_ref(name))
+    }
+
+    fn find_branch_commit(&self, name: &str) -> Option<git2::Commit> {
+        if !self._repo.is_some() {
+            return None;
+        }
+
+        self._repo
+            .as_ref()
+            .unwrap()
+            .find_reference(branch_ref(name).as_str())
+            .and_then(|r| r.peel_to_commit())
+            .ok()
+    }
 
-        repo.commit(
-            Some("HEAD"),
-            signature.as_ref().unwrap(),
-            signature.as_ref().unwrap(),
-            message,
-            tree.as_ref().unwrap(),
-            &[parent_commit.as_ref().unwrap()],
-        )
-        .map_err(|_| StupidDbError::CommitFailed)
+    pub(super) fn find_revision_commit(&self, revision: &str) -> Option<git2::Commit> {
+        if !self._repo.is_some() {
+            return None;
+        }
+
+        self._repo
+            .as_ref()
+            .unwrap()
+            .revparse_single(revision)
+            .and_then(|o| o.peel_to_commit())
+            .ok()
     }
 
-    fn find_last_commit(&self) -> Option<git2::Commit> {
+    pub(super) fn find_last_commit(&self) -> Option<git2::Commit> {
         if !self._repo.is_some() {
             return None;
         }
@@ -109,41 +1129,286 @@ impl StupidRepository {
         if !self._repo.is_some() {
             return Err(StupidDbError::RepoNotInitialized);
         }
+        // A cached repository keeps its index in memory, another handle may
+        // have written the index file since
         self._repo
             .as_ref()
             .unwrap()
             .index()
+            .and_then(|mut index| index.read(false).map(|_| index))
             .map_err(|_| StupidDbError::IndexFetchFailed)
     }
 
-    pub fn push(&self) -> Result<(), StupidDbError> {
+    /// Pushes to the requested remote, or to every remote when asked to.
+    ///
+    /// Pushing to a single remote fails with its error, pushing to all
+    /// remotes reports the error of each remote in its result instead.
+    /// Webhooks are told how it went either way.
+    pub fn push(
+        &self,
+        request: &models::StupidPushRequest,
+    ) -> Result<Vec<models::StupidRemotePushResult>, StupidDbError> {
+        let push_result = self.push_remotes(request);
+        let (event, data) = match push_result.as_ref() {
+            Ok(results) if results.iter().all(|r| r.error.is_none()) => (
+                models::StupidWebhookEvent::PushSucceeded,
+                serde_json::to_value(results).unwrap_or_default(),
+            ),
+            Ok(results) => (
+                models::StupidWebhookEvent::PushFailed,
+                serde_json::to_value(results).unwrap_or_default(),
+            ),
+            Err(e) => (
+                models::StupidWebhookEvent::PushFailed,
+                serde_json::json!({ "error": format!("{:?}", e) }),
+            ),
+        };
+        webhook::notify(self, event, data);
+
+        push_result
+    }
+
+    fn push_remotes(
+        &self,
+        request: &models::StupidPushRequest,
+    ) -> Result<Vec<models::StupidRemotePushResult>, StupidDbError> {
         if !self._repo.is_some() {
             return Err(StupidDbError::RepoNotInitialized);
         }
 
+        if !request.all_remotes {
+            let name = request
+                .remote
+                .as_ref()
+                .map_or(DEFAULT_REMOTE, |r| r.as_str());
+            return self.push_remote(name, request).map(|references| {
+                vec![models::StupidRemotePushResult {
+                    remote: String::from(name),
+                    error: None,
+                    references,
+                }]
+            });
+        }
+
+        let results = self
+            .remotes
+            .iter()
+            .map(
+                |remote| match self.push_remote(remote.name.as_str(), request) {
+                    Ok(references) => models::StupidRemotePushResult {
+                        remote: remote.name.to_string(),
+                        error: None,
+                        references,
+                    },
+                    Err(e) => models::StupidRemotePushResult {
+                        remote: remote.name.to_string(),
+                        error: Some(format!("{:?}", e)),
+                        references: Vec::new(),
+                    },
+                },
+            )
+            .collect();
+
+        Ok(results)
+    }
+
+    /// Pushes the requested refspecs, falling back to the refspecs configured
+    /// for the remote and finally to the branch HEAD points at.
+    fn push_remote(
+        &self,
+        remote_name: &str,
+        request: &models::StupidPushRequest,
+    ) -> Result<Vec<models::StupidPushResult>, StupidDbError> {
         let repo = self._repo.as_ref().unwrap();
-        let remote_result = repo.find_remote("origin");
+        let remote_result = repo.find_remote(remote_name);
         if remote_result.is_err() {
-            return Err(StupidDbError::NoOriginRemote);
+            return match remote_name {
+                DEFAULT_REMOTE => Err(StupidDbError::NoOriginRemote),
+                _ => Err(StupidDbError::RemoteNotFound),
+            };
         }
 
         let mut remote = remote_result.unwrap();
 
-        let mut callbacks = git2::RemoteCallbacks::new();
-        callbacks.credentials(|_, user_from_url, cred_type| {
-            let user = user_from_url.unwrap_or(DEFAULT_GIT_SSH_USERNAME);
-            if cred_type.contains(git2::CredentialType::USERNAME) {
-                git2::Cred::username(user)
-            } else {
-                git2::Cred::ssh_key_from_agent(user)
+        let refspecs_result = self.push_refspecs(&remote, request);
+        if refspecs_result.is_err() {
+            return Err(refspecs_result.err().unwrap());
+        }
+
+        let mut refspecs = refspecs_result.unwrap();
+        let mut results: Vec<models::StupidPushResult> = Vec::new();
+
+        if !request.force_with_lease.is_empty() {
+            let stale_result = self.stale_leases(&mut remote, &request.force_with_lease);
+            if stale_result.is_err() {
+                return Err(stale_result.err().unwrap());
+            }
+
+            let stale = stale_result.unwrap();
+            for (reference, _) in request.force_with_lease.iter() {
+                // A lease on a ref also named in the plain refspecs would be
+                // pushed twice, once without force.
+                refspecs.retain(|r| refspec_destination(r) != reference);
+                if stale.contains(reference) {
+                    results.push(models::StupidPushResult {
+                        reference: reference.to_string(),
+                        status: Some(String::from("stale info")),
+                    });
+                } else {
+                    refspecs.push(format!("+{}:{}", reference, reference));
+                }
             }
+        }
+
+        if refspecs.is_empty() {
+            return Ok(results);
+        }
+
+        let updates = std::cell::RefCell::new(Vec::new());
+        let mut callbacks = credentials::callbacks(self.credentials.as_ref(), repo.config().ok());
+        callbacks.push_update_reference(|reference, status| {
+            updates.borrow_mut().push(models::StupidPushResult {
+                reference: String::from(reference),
+                status: status.map(|s| String::from(s)),
+            });
+            Ok(())
         });
+
         let mut opts = git2::PushOptions::new();
         opts.remote_callbacks(callbacks);
 
-        remote
-            .push(&["refs/heads/master"], Some(&mut opts))
-            .map_err(|_| StupidDbError::RemotePushFailed)
+        let push_result = remote
+            .push(refspecs.as_slice(), Some(&mut opts))
+            .map_err(|_| StupidDbError::RemotePushFailed);
+        if push_result.is_err() {
+            return Err(push_result.err().unwrap());
+        }
+
+        drop(opts);
+        results.append(&mut updates.into_inner());
+        Ok(results)
+    }
+
+    fn push_refspecs(
+        &self,
+        remote: &git2::Remote,
+        request: &models::StupidPushRequest,
+    ) -> Result<Vec<String>, StupidDbError> {
+        let repo = self._repo.as_ref().unwrap();
+        let mut refspecs: Vec<String> = match request.refspecs.as_ref() {
+            Some(r) => r.clone(),
+            None => Vec::new(),
+        };
+
+        if refspecs.is_empty() && request.force_with_lease.is_empty() {
+            refspecs = match remote.push_refspecs() {
+                Err(_) => Vec::new(),
+                Ok(configured) => configured.iter().flatten().map(String::from).collect(),
+            };
+        }
+
+        if refspecs.is_empty() && request.force_with_lease.is_empty() {
+            let head = repo.head();
+            if head.is_err() {
+                return Err(StupidDbError::HeadFetchFailed);
+            }
+
+            let head_ref = head.unwrap();
+            let branch = head_ref.name();
+            if branch.is_none() {
+                return Err(StupidDbError::HeadFetchFailed);
+            }
+
+            refspecs.push(String::from(branch.unwrap()));
+        }
+
+        if request.tags {
+            let tags = repo.tag_names(None);
+            if tags.is_err() {
+                return Err(StupidDbError::TagFetchFailed);
+            }
+
+            for tag in tags.unwrap().iter().flatten() {
+                refspecs.push(format!("refs/tags/{}:refs/tags/{}", tag, tag));
+            }
+        }
+
+        Ok(refspecs)
+    }
+
+    /// Returns the leased refs whose value on the remote is not the expected one.
+    ///
+    /// The check and the following push are not atomic, the lease only
+    /// narrows the window in which a concurrent push gets overwritten.
+    fn stale_leases(
+        &self,
+        remote: &mut git2::Remote,
+        leases: &HashMap<String, String>,
+    ) -> Result<Vec<String>, StupidDbError> {
+        let repo = self._repo.as_ref().unwrap();
+        let callbacks = credentials::callbacks(self.credentials.as_ref(), repo.config().ok());
+        let connection = remote.connect_auth(git2::Direction::Push, Some(callbacks), None);
+        if connection.is_err() {
+            return Err(StupidDbError::RemoteConnectFailed);
+        }
+
+        let connection = connection.unwrap();
+        let heads = connection.list();
+        if heads.is_err() {
+            return Err(StupidDbError::RemoteConnectFailed);
+        }
+
+        let heads = heads.unwrap();
+        let stale = leases
+            .iter()
+            .filter(|(reference, expected)| {
+                let current = heads
+                    .iter()
+                    .find(|h| h.name() == reference.as_str())
+                    .map(|h| h.oid());
+                match (current, git2::Oid::from_str(expected.as_str())) {
+                    (None, Ok(e)) => !e.is_zero(),
+                    (None, Err(_)) => !expected.is_empty(),
+                    (Some(c), Ok(e)) => c != e,
+                    (Some(_), Err(_)) => true,
+                }
+            })
+            .map(|(reference, _)| reference.to_string())
+            .collect();
+
+        Ok(stale)
+    }
+
+    /// Replaces the push refspecs configured for a remote
+    pub fn set_push_refspecs(
+        &self,
+        remote: &str,
+        refspecs: &[String],
+    ) -> Result<(), StupidDbError> {
+        if !self._repo.is_some() {
+            return Err(StupidDbError::RepoNotInitialized);
+        }
+
+        let repo = self._repo.as_ref().unwrap();
+        let config = repo.config();
+        if config.is_err() {
+            return Err(StupidDbError::RemoteUrlFailed);
+        }
+
+        // Removing fails when no push refspec was configured yet
+        let _ = config
+            .unwrap()
+            .remove_multivar(format!("remote.{}.push", remote).as_str(), ".*");
+
+        for refspec in refspecs {
+            let add_result = repo.remote_add_push(remote, refspec.as_str());
+            if add_result.is_err() {
+                return Err(StupidDbError::RemoteUrlFailed);
+            }
+        }
+
+        self.stale.set(true);
+        Ok(())
     }
 
     pub fn blob(&self, data: &[u8]) -> Result<git2::Oid, StupidDbError> {
@@ -161,31 +1426,39 @@ impl StupidRepository {
         match Repository::open(Path::new(&self.path)) {
             Err(_) => Err(StupidDbError::RepoPathInvalid),
             Ok(repo) => {
-                let origin = match repo.remotes() {
-                    Err(_) => None,
-                    Ok(ra) => {
-                        if ra.len() > 0 {
-                            let remote = String::from(ra.get(0).unwrap());
-                            Some(remote)
-                        } else {
-                            None
-                        }
-                    }
+                self.remotes = match repo.remotes() {
+                    Err(_) => Vec::new(),
+                    Ok(names) => names
+                        .iter()
+                        .flatten()
+                        .filter_map(|name| repo.find_remote(name).ok())
+                        .map(|r| models::StupidRemote {
+                            name: String::from(r.name().unwrap_or_default()),
+                            url: String::from(r.url().unwrap_or_default()),
+                            push_refspecs: r
+                                .push_refspecs()
+                                .ok()
+                                .map(|p| p.iter().flatten().map(String::from).collect()),
+                        })
+                        .collect(),
                 };
 
-                if origin.is_some() {
-                    self.origin = match repo.find_remote(origin.as_ref().unwrap()) {
-                        Err(_) => None,
-                        Ok(r) => {
-                            let pu = r.url();
-                            if pu.is_some() {
-                                Some(String::from(r.url().unwrap()))
-                            } else {
-                                None
-                            }
-                        }
-                    };
-                }
+                self.origin = self
+                    .remote(DEFAULT_REMOTE)
+                    .map(|r| String::from(r.url.as_str()));
+
+                self.mirror = match repo.config() {
+                    Err(_) => None,
+                    Ok(config) => match config.get_bool(MIRROR_KEY) {
+                        Ok(true) => Some(models::StupidMirror {
+                            interval: config
+                                .get_i64(MIRROR_INTERVAL_KEY)
+                                .map_or(DEFAULT_MIRROR_INTERVAL, |i| i as u64),
+                            remote: config.get_string(MIRROR_REMOTE_KEY).ok(),
+                        }),
+                        _ => None,
+                    },
+                };
 
                 self._repo = Some(repo);
                 Ok(self._repo.as_ref().unwrap())
@@ -193,6 +1466,367 @@ impl StupidRepository {
         }
     }
 
+    /// The opened git repository, for modules extending StupidRepository
+    pub(super) fn repository(&self) -> Result<&Repository, StupidDbError> {
+        match self._repo.as_ref() {
+            Some(repo) => Ok(repo),
+            None => Err(StupidDbError::RepoNotInitialized),
+        }
+    }
+
+    /// Announces the commits a ref was moved over without create_commit,
+    /// by a fast-forward or a reset, and moves the indexes along.
+    ///
+    /// Change events, and so webhooks and autopush, then see every commit
+    /// whichever way it reached the ref.
+    fn ref_moved(&self, reference: &str, previous: Option<&str>) {
+        let changes = self.changes_since(reference, previous);
+        if changes.is_ok() {
+            changes.unwrap().into_iter().for_each(events::publish);
+        }
+
+        self.update_indexes(reference);
+    }
+
+    /// Moves the secondary indexes of a ref and the full-text index along
+    /// with it. A failure leaves them stale, queries then read the documents
+    /// instead.
+    fn update_indexes(&self, reference: &str) {
+        let index_result = index::update(self, reference).and_then(|_| search::follow(self));
+        if index_result.is_err() {
+            eprintln!(
+                "Indexes of {} are stale: {:?}",
+                self.name(),
+                index_result.err()
+            );
+        }
+    }
+
+    /// Name of the repository, the name of its directory
+    pub fn name(&self) -> String {
+        Path::new(self.path.as_str())
+            .file_name()
+            .and_then(|n| n.to_str())
+            .map_or(String::new(), String::from)
+    }
+
+    /// Whether the remotes or mirror settings changed since the repository
+    /// was opened, the fields then no longer reflect its config
+    pub fn is_stale(&self) -> bool {
+        self.stale.get()
+    }
+
+    pub fn remote(&self, name: &str) -> Option<&models::StupidRemote> {
+        self.remotes.iter().find(|r| r.name == name)
+    }
+
+    pub fn add_remote(&self, remote: &models::StupidRemote) -> Result<(), StupidDbError> {
+        if !self._repo.is_some() {
+            return Err(StupidDbError::RepoNotInitialized);
+        }
+
+        if self.remote(remote.name.as_str()).is_some() {
+            return Err(StupidDbError::RemoteExists);
+        }
+
+        let repo = self._repo.as_ref().unwrap();
+        let add_result = repo.remote(remote.name.as_str(), remote.url.as_str());
+        if add_result.is_err() {
+            return Err(StupidDbError::RemoteUrlFailed);
+        }
+
+        self.stale.set(true);
+
+        match remote.push_refspecs.as_ref() {
+            Some(refspecs) => self.set_push_refspecs(remote.name.as_str(), refspecs),
+            None => Ok(()),
+        }
+    }
+
+    /// Updates url and push refspecs of a remote, renaming it when the
+    /// remote carries a new name
+    pub fn update_remote(
+        &self,
+        name: &str,
+        remote: &models::StupidRemote,
+    ) -> Result<(), StupidDbError> {
+        if !self._repo.is_some() {
+            return Err(StupidDbError::RepoNotInitialized);
+        }
+
+        if self.remote(name).is_none() {
+            return Err(StupidDbError::RemoteNotFound);
+        }
+
+        if name != remote.name {
+            if self.remote(remote.name.as_str()).is_some() {
+                return Err(StupidDbError::RemoteExists);
+            }
+
+            let rename_result = self
+                ._repo
+                .as_ref()
+                .unwrap()
+                .remote_rename(name, remote.name.as_str());
+            if rename_result.is_err() {
+                return Err(StupidDbError::RemoteUrlFailed);
+            }
+
+            self.stale.set(true);
+        }
+
+        let url_result = self.set_remoteurl(remote.name.as_str(), remote.url.as_str());
+        if url_result.is_err() {
+            return url_result;
+        }
+
+        match remote.push_refspecs.as_ref() {
+            Some(refspecs) => self.set_push_refspecs(remote.name.as_str(), refspecs),
+            None => Ok(()),
+        }
+    }
+
+    pub fn remove_remote(&self, name: &str) -> Result<(), StupidDbError> {
+        if !self._repo.is_some() {
+            return Err(StupidDbError::RepoNotInitialized);
+        }
+
+        if self.remote(name).is_none() {
+            return Err(StupidDbError::RemoteNotFound);
+        }
+
+        self.stale.set(true);
+        self._repo
+            .as_ref()
+            .unwrap()
+            .remote_delete(name)
+            .map_err(|_| StupidDbError::RemoteDeleteFailed)
+    }
+
+    /// Flags the repository as mirror of a remote, or removes the flag
+    pub fn set_mirror(&self, mirror: Option<&models::StupidMirror>) -> Result<(), StupidDbError> {
+        if !self._repo.is_some() {
+            return Err(StupidDbError::RepoNotInitialized);
+        }
+
+        let config_result = self._repo.as_ref().unwrap().config();
+        if config_result.is_err() {
+            return Err(StupidDbError::ConfigFailed);
+        }
+
+        let mut config = config_result.unwrap();
+        let set_result = match mirror {
+            None => config.set_bool(MIRROR_KEY, false),
+            Some(m) => config
+                .set_bool(MIRROR_KEY, true)
+                .and_then(|_| config.set_i64(MIRROR_INTERVAL_KEY, m.interval as i64))
+                .and_then(|_| match m.remote.as_ref() {
+                    Some(r) => config.set_str(MIRROR_REMOTE_KEY, r.as_str()),
+                    // Removing fails when no remote was set before
+                    None => Ok(config.remove(MIRROR_REMOTE_KEY).unwrap_or(())),
+                }),
+        };
+
+        self.stale.set(true);
+        set_result.map_err(|_| StupidDbError::ConfigFailed)
+    }
+
+    /// Fetches the mirrored remote and resets HEAD, index and working tree
+    /// to the branch HEAD points at on the remote.
+    ///
+    /// This is the only way the refs of a mirror move, everything else is
+    /// refused by update_ref.
+    pub fn sync_mirror(&self) -> Result<git2::Oid, StupidDbError> {
+        if !self._repo.is_some() {
+            return Err(StupidDbError::RepoNotInitialized);
+        }
+
+        let remote_name = self
+            .mirror
+            .as_ref()
+            .and_then(|m| m.remote.as_ref())
+            .map_or(DEFAULT_REMOTE, |r| r.as_str());
+
+        let fetch_result = self.fetch(remote_name);
+        if fetch_result.is_err() {
+            return Err(fetch_result.err().unwrap());
+        }
+
+        // The mirror follows the branch HEAD points at on the remote, which
+        // need not be the one it was created with
+        let repo = self._repo.as_ref().unwrap();
+        let branch = self
+            .remote_head_branch(remote_name)
+            .or_else(|| self.head_branch());
+        if branch.is_none() {
+            return Err(StupidDbError::HeadFetchFailed);
+        }
+
+        let branch = branch.unwrap();
+        let tracking = format!(
+            "refs/remotes/{}/{}",
+            remote_name,
+            branch.trim_start_matches("refs/heads/")
+        );
+        let previous = self.find_last_commit().map(|c| c.id().to_string());
+        let upstream = repo
+            .find_reference(tracking.as_str())
+            .and_then(|r| r.peel(git2::ObjectType::Commit));
+        if upstream.is_err() {
+            return Err(StupidDbError::CommitFetchFailed);
+        }
+
+        let upstream = upstream.unwrap();
+        if self.head_branch().as_ref() != Some(&branch) {
+            let moved = repo
+                .reference(
+                    branch.as_str(),
+                    upstream.id(),
+                    true,
+                    "mirror: follow remote HEAD",
+                )
+                .and_then(|_| repo.set_head(branch.as_str()));
+            if moved.is_err() {
+                return Err(StupidDbError::ResetFailed);
+            }
+        }
+
+        if repo.reset(&upstream, git2::ResetType::Hard, None).is_err() {
+            return Err(StupidDbError::ResetFailed);
+        }
+
+        self.ref_moved(branch.as_str(), previous.as_ref().map(|p| p.as_str()));
+
+        Ok(upstream.id())
+    }
+
+    /// Branch HEAD points at on a remote, None when it cannot be reached
+    fn remote_head_branch(&self, remote_name: &str) -> Option<String> {
+        let repo = self._repo.as_ref()?;
+        let mut remote = repo.find_remote(remote_name).ok()?;
+        let callbacks = credentials::callbacks(self.credentials.as_ref(), repo.config().ok());
+        let connection = remote
+            .connect_auth(git2::Direction::Fetch, Some(callbacks), None)
+            .ok()?;
+        let branch = connection.default_branch().ok()?;
+        branch.as_str().map(String::from)
+    }
+
+    /// Fetches the configured refspecs of a remote
+    pub fn fetch(&self, remote_name: &str) -> Result<(), StupidDbError> {
+        if !self._repo.is_some() {
+            return Err(StupidDbError::RepoNotInitialized);
+        }
+
+        let repo = self._repo.as_ref().unwrap();
+        let remote_result = repo.find_remote(remote_name);
+        if remote_result.is_err() {
+            return match remote_name {
+                DEFAULT_REMOTE => Err(StupidDbError::NoOriginRemote),
+                _ => Err(StupidDbError::RemoteNotFound),
+            };
+        }
+
+        let callbacks = credentials::callbacks(self.credentials.as_ref(), repo.config().ok());
+        let mut opts = git2::FetchOptions::new();
+        opts.remote_callbacks(callbacks);
+
+        let no_refspecs: &[&str] = &[];
+        remote_result
+            .unwrap()
+            .fetch(no_refspecs, Some(&mut opts), None)
+            .map_err(|_| StupidDbError::RemoteFetchFailed)
+    }
+
+    /// Name of the branch HEAD points at, also when it has no commit yet
+    pub fn head_branch(&self) -> Option<String> {
+        if !self._repo.is_some() {
+            return None;
+        }
+
+        self._repo
+            .as_ref()
+            .unwrap()
+            .find_reference("HEAD")
+            .ok()
+            .and_then(|h| h.symbolic_target().map(String::from))
+    }
+
+    /// Summarizes the state of the repository
+    pub fn info(&self) -> Result<info::StupidRepositoryInfo, StupidDbError> {
+        if !self._repo.is_some() {
+            return Err(StupidDbError::RepoNotInitialized);
+        }
+
+        let index_result = self.index();
+        if index_result.is_err() {
+            return Err(index_result.err().unwrap());
+        }
+
+        let repo = self._repo.as_ref().unwrap();
+        let head = self.find_last_commit();
+
+        let commit_length = match head.as_ref() {
+            None => 0,
+            Some(h) => {
+                let revwalk = repo.revwalk().and_then(|mut r| r.push(h.id()).map(|_| r));
+                if revwalk.is_err() {
+                    return Err(StupidDbError::CommitFetchFailed);
+                }
+                revwalk.unwrap().count() as i64
+            }
+        };
+
+        let upstream = self.head_branch().and_then(|b| {
+            let tracking = format!(
+                "refs/remotes/{}/{}",
+                DEFAULT_REMOTE,
+                b.trim_start_matches("refs/heads/")
+            );
+            repo.refname_to_id(tracking.as_str()).ok()
+        });
+
+        let ahead_behind = match (head.as_ref(), upstream) {
+            (Some(h), Some(u)) => repo.graph_ahead_behind(h.id(), u).ok(),
+            _ => None,
+        };
+
+        Ok(info::StupidRepositoryInfo {
+            index_length: index_result.unwrap().len() as i32,
+            commit_length,
+            head: head.as_ref().map(|h| h.id().to_string()),
+            head_time: head.as_ref().map(|h| h.time().seconds()),
+            size: dir_size(Path::new(self.path.as_str())),
+            remotes: self.remotes.clone(),
+            ahead: ahead_behind.map(|(a, _)| a),
+            behind: ahead_behind.map(|(_, b)| b),
+            mirror: mirror::status(self),
+        })
+    }
+
+    /// Summarizes the repository for listings, cheaper than info
+    pub fn summary(&self, name: &str) -> info::StupidRepositorySummary {
+        info::StupidRepositorySummary {
+            name: String::from(name),
+            origin: self.origin.clone(),
+            head: self.find_last_commit().map(|h| h.id().to_string()),
+            size: dir_size(Path::new(self.path.as_str())),
+        }
+    }
+
+    /// Directory for data stupiddb keeps next to the git objects
+    pub fn data_dir(&self) -> Result<std::path::PathBuf, StupidDbError> {
+        if !self._repo.is_some() {
+            return Err(StupidDbError::RepoNotInitialized);
+        }
+
+        let dir = self._repo.as_ref().unwrap().path().join("stupiddb");
+        match std::fs::create_dir_all(&dir) {
+            Ok(_) => Ok(dir),
+            Err(_) => Err(StupidDbError::RepoPathInvalid),
+        }
+    }
+
     pub fn init(&self) -> Result<(), StupidDbError> {
         let p = Path::new(self.path.as_str());
         self.check_path(p)
@@ -203,6 +1837,7 @@ impl StupidRepository {
             return Err(StupidDbError::RepoNotInitialized);
         }
         let repo = self._repo.as_ref().unwrap();
+        self.stale.set(true);
         repo.remote_set_url(remote, url)
             .map_err(|_| StupidDbError::RemoteUrlFailed)
     }
@@ -233,33 +1868,69 @@ impl StupidRepository {
             return Err(index_result.err().unwrap());
         }
 
-        let repo = self._repo.as_ref().unwrap();
         let mut index = index_result.unwrap();
 
-        let signature = repo.signature();
-        if signature.is_err() {
-            return Err(StupidDbError::SignatureFailed);
-        }
-
         let write_result = index.write_tree();
         if write_result.is_err() {
             return Err(StupidDbError::WriteTreeFailed);
         }
 
-        let tree = repo.find_tree(write_result.unwrap());
-        if tree.is_err() {
+        let tree = self.find_tree(write_result.unwrap());
+        if tree.is_none() {
             return Err(StupidDbError::TreeFetchFailed);
         }
 
-        repo.commit(
-            Some("HEAD"),
-            signature.as_ref().unwrap(),
-            signature.as_ref().unwrap(),
-            "Initial commit",
-            tree.as_ref().unwrap(),
-            &[],
-        )
-        .map_err(|_| StupidDbError::CommitFailed)
-        .map(|_| ())
+        self.create_commit("HEAD", "Initial commit", tree.as_ref().unwrap(), &[])
+            .map(|_| ())
+    }
+}
+
+fn branch_ref(name: &str) -> String {
+    format!("refs/heads/{}", name)
+}
+
+/// Content of an annotated tag object
+fn tag_buffer(
+    commit: &git2::Commit,
+    name: &str,
+    tagger: &git2::Signature,
+    message: &str,
+) -> String {
+    let when = tagger.when();
+    let offset = when.offset_minutes().abs();
+    format!(
+        "object {}\ntype commit\ntag {}\ntagger {} <{}> {} {}{:02}{:02}\n\n{}",
+        commit.id(),
+        name,
+        tagger.name().unwrap_or_default(),
+        tagger.email().unwrap_or_default(),
+        when.seconds(),
+        when.sign(),
+        offset / 60,
+        offset % 60,
+        message
+    )
+}
+
+/// Ref a refspec updates on the remote, `src` standing for `src:src`
+fn refspec_destination(refspec: &str) -> &str {
+    let refspec = refspec.trim_start_matches('+');
+    refspec.rsplit(':').next().unwrap_or(refspec)
+}
+
+fn dir_size(path: &Path) -> u64 {
+    let entries = std::fs::read_dir(path);
+    if entries.is_err() {
+        return 0;
     }
+
+    entries
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .map(|e| match e.metadata() {
+            Ok(m) if m.is_dir() => dir_size(e.path().as_path()),
+            Ok(m) => m.len(),
+            Err(_) => 0,
+        })
+        .sum()
 }
@@ -0,0 +1,556 @@
+//! Full-text search over the string values of documents.
+//!
+//! The inverted index maps terms to the documents holding them and, like
+//! the secondary indexes, reflects the tree of the commit HEAD pointed at
+//! when it was last updated. It is built by the first search of a
+//! repository and follows its commits from then on.
+//!
+//! Terms are spread over shards by their digest, each kept in a file of
+//! its own next to a manifest naming the commit. Searches only load the
+//! shards of their terms and commits only rewrite the shards of the terms
+//! whose counts they changed.
+//!
+//! Documents are indexed as stored, the values encryption seals are left
+//! out of the index and cannot be searched.
+
+use super::encryption;
+use super::error::StupidDbError;
+use super::models::{StupidHighlight, StupidSearchHit};
+use super::repository::StupidRepository;
+use lazy_static::lazy_static;
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+const SEARCH_DIR: &str = "search";
+const MANIFEST_FILE: &str = "manifest.json";
+/// Characters of a string shown around the first match
+const SNIPPET_CONTEXT: usize = 40;
+const HIGHLIGHT_START: &str = "<em>";
+const HIGHLIGHT_END: &str = "</em>";
+
+lazy_static! {
+    /// Commits on different threads update the same shards, searches must
+    /// not read them halfway through an update
+    static ref SEARCH_LOCK: RwLock<()> = RwLock::new(());
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Manifest {
+    /// Commit the documents were read from
+    commit: Option<String>,
+    /// Number of indexed documents
+    documents: u32,
+}
+
+/// The terms whose digest starts with the same byte
+#[derive(Serialize, Deserialize, Default)]
+struct Shard {
+    /// Occurrences by document name by term
+    terms: HashMap<String, BTreeMap<String, u32>>,
+}
+
+impl Shard {
+    fn set(&mut self, term: &str, name: &str, count: Option<u32>) {
+        match count {
+            Some(count) => {
+                self.terms
+                    .entry(String::from(term))
+                    .or_default()
+                    .insert(String::from(name), count);
+            }
+            None => {
+                let empty = match self.terms.get_mut(term) {
+                    Some(documents) => {
+                        documents.remove(name);
+                        documents.is_empty()
+                    }
+                    None => false,
+                };
+                if empty {
+                    self.terms.remove(term);
+                }
+            }
+        }
+    }
+}
+
+/// Documents at HEAD holding any of the terms of the query, best matches
+/// first. Documents not `visible` to the caller are left out.
+pub fn search(
+    repo: &StupidRepository,
+    query: &str,
+    limit: usize,
+    visible: &dyn Fn(&str) -> bool,
+) -> Result<Vec<StupidSearchHit>, StupidDbError> {
+    let query_terms = terms(query);
+    if query_terms.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let head = repo.find_last_commit();
+    if head.is_none() {
+        return Ok(Vec::new());
+    }
+
+    let head = head.unwrap();
+    let updated = update(repo);
+    if updated.is_err() {
+        return Err(updated.err().unwrap());
+    }
+
+    let guard = SEARCH_LOCK.read().unwrap();
+    let manifest = load_manifest(repo);
+    if manifest.is_err() {
+        return Err(manifest.err().unwrap());
+    }
+
+    let mut shards: HashMap<String, Shard> = HashMap::new();
+    for term in query_terms.iter() {
+        let file = shard_file(term);
+        if shards.contains_key(&file) {
+            continue;
+        }
+
+        let shard = load_shard(repo, file.as_str());
+        if shard.is_err() {
+            return Err(shard.err().unwrap());
+        }
+
+        shards.insert(file, shard.unwrap());
+    }
+    drop(guard);
+
+    // Terms found in few documents weigh more than common ones
+    let total = manifest.unwrap().documents as f64;
+    let mut scores: HashMap<&String, f64> = HashMap::new();
+    for term in query_terms.iter() {
+        let documents = match shards.get(&shard_file(term)).unwrap().terms.get(term) {
+            Some(d) => d,
+            None => continue,
+        };
+
+        let frequency = documents.len() as f64;
+        let weight = (1.0 + (total - frequency + 0.5) / (frequency + 0.5)).ln();
+        for (name, count) in documents.iter() {
+            let count = *count as f64;
+            *scores.entry(name).or_default() += weight * count / (count + 1.2);
+        }
+    }
+
+    let mut ranked: Vec<(&String, f64)> = scores
+        .into_iter()
+        .filter(|(name, _)| visible(name.as_str()))
+        .collect();
+    ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then(a.0.cmp(b.0)));
+    ranked.truncate(limit);
+
+    let tree = head.tree();
+    if tree.is_err() {
+        return Err(StupidDbError::TreeFetchFailed);
+    }
+
+    let tree = tree.unwrap();
+    let mut hits = Vec::new();
+    for (name, score) in ranked {
+        let entry = tree.get_name(name.as_str());
+        if entry.is_none() {
+            continue;
+        }
+
+        // Highlighted as indexed, sealed values match nothing
+        let id = entry.unwrap().id();
+        let content = repo.read_stored_value(id);
+        if content.is_err() {
+            return Err(content.err().unwrap());
+        }
+
+        hits.push(StupidSearchHit {
+            name: name.to_string(),
+            id: id.to_string(),
+            score,
+            highlights: highlights(&content.unwrap(), &query_terms),
+        });
+    }
+
+    Ok(hits)
+}
+
+/// Builds the inverted index again from the tree HEAD points at
+pub fn rebuild(repo: &StupidRepository) -> Result<(), StupidDbError> {
+    let _guard = SEARCH_LOCK.write().unwrap();
+    build(repo)
+}
+
+/// Moves an existing inverted index to the commit HEAD points at now
+pub fn follow(repo: &StupidRepository) -> Result<(), StupidDbError> {
+    let dir = search_dir(repo);
+    if dir.is_err() {
+        return Err(dir.err().unwrap());
+    }
+
+    // Repositories never searched have no index to keep up to date
+    if !dir.unwrap().join(MANIFEST_FILE).exists() {
+        return Ok(());
+    }
+
+    update(repo)
+}
+
+fn update(repo: &StupidRepository) -> Result<(), StupidDbError> {
+    let _guard = SEARCH_LOCK.write().unwrap();
+    let manifest = load_manifest(repo);
+    if manifest.is_err() {
+        return Err(manifest.err().unwrap());
+    }
+
+    let mut manifest = manifest.unwrap();
+    let head = repo.find_last_commit();
+    if head.is_none() {
+        return Ok(());
+    }
+
+    let head = head.unwrap();
+    let head_id = head.id().to_string();
+    if manifest.commit.as_ref() == Some(&head_id) {
+        return Ok(());
+    }
+
+    let old_tree = manifest
+        .commit
+        .as_ref()
+        .and_then(|c| repo.find_commit_tree(c.as_str()));
+    if old_tree.is_none() {
+        // Without the old tree there is nothing to diff against
+        return build(repo);
+    }
+
+    let deltas = repo.diff_documents(old_tree.as_ref(), head.tree().ok().as_ref());
+    if deltas.is_err() {
+        return Err(deltas.err().unwrap());
+    }
+
+    // Only the terms whose count in a document changed are touched
+    let mut changes: BTreeMap<String, Vec<(String, String, Option<u32>)>> = BTreeMap::new();
+    for delta in deltas.unwrap() {
+        let old = delta
+            .old
+            .and_then(|old| repo.read_stored_value(old).ok())
+            .map(|old| counts(&old));
+        let new = match delta.new {
+            Some(new) => repo.read_stored_value(new).map(|new| Some(counts(&new))),
+            None => Ok(None),
+        };
+        if new.is_err() {
+            return Err(new.err().unwrap());
+        }
+
+        let new = new.unwrap();
+        match (old.is_some(), new.is_some()) {
+            (false, true) => manifest.documents += 1,
+            (true, false) => manifest.documents = manifest.documents.saturating_sub(1),
+            _ => {}
+        }
+
+        let old = old.unwrap_or_default();
+        let new = new.unwrap_or_default();
+        let changed: BTreeSet<&String> = old
+            .keys()
+            .chain(new.keys())
+            .filter(|term| old.get(*term) != new.get(*term))
+            .collect();
+        for term in changed {
+            changes.entry(shard_file(term)).or_default().push((
+                term.to_string(),
+                delta.name.to_string(),
+                new.get(term).cloned(),
+            ));
+        }
+    }
+
+    for (file, shard_changes) in changes {
+        let shard = load_shard(repo, file.as_str());
+        if shard.is_err() {
+            return Err(shard.err().unwrap());
+        }
+
+        let mut shard = shard.unwrap();
+        for (term, name, count) in shard_changes {
+            shard.set(term.as_str(), name.as_str(), count);
+        }
+
+        let saved = save_shard(repo, file.as_str(), &shard);
+        if saved.is_err() {
+            return Err(saved.err().unwrap());
+        }
+    }
+
+    // Shards saved before a failure here are applied again by the next
+    // update, setting the same counts twice leaves them the same
+    manifest.commit = Some(head_id);
+    save_manifest(repo, &manifest)
+}
+
+/// Builds every shard from the tree HEAD points at, replacing the previous
+/// ones
+fn build(repo: &StupidRepository) -> Result<(), StupidDbError> {
+    let dir = search_dir(repo);
+    if dir.is_err() {
+        return Err(dir.err().unwrap());
+    }
+
+    let dir = dir.unwrap();
+    if dir.exists() && fs::remove_dir_all(&dir).is_err() {
+        return Err(StupidDbError::IndexStoreFailed);
+    }
+
+    let mut manifest = Manifest::default();
+    let mut shards: BTreeMap<String, Shard> = BTreeMap::new();
+    let head = repo.find_last_commit();
+    if let Some(head) = head.as_ref() {
+        let tree = head.tree();
+        if tree.is_err() {
+            return Err(StupidDbError::TreeFetchFailed);
+        }
+
+        for entry in tree.unwrap().iter() {
+            let name = entry.name();
+            if name.is_none() || entry.kind() != Some(git2::ObjectType::Blob) {
+                continue;
+            }
+
+            let content = repo.read_stored_value(entry.id());
+            if content.is_err() {
+                return Err(content.err().unwrap());
+            }
+
+            for (term, count) in counts(&content.unwrap()) {
+                shards.entry(shard_file(term.as_str())).or_default().set(
+                    term.as_str(),
+                    name.unwrap(),
+                    Some(count),
+                );
+            }
+            manifest.documents += 1;
+        }
+
+        manifest.commit = Some(head.id().to_string());
+    }
+
+    for (file, shard) in shards.iter() {
+        let saved = save_shard(repo, file.as_str(), shard);
+        if saved.is_err() {
+            return Err(saved.err().unwrap());
+        }
+    }
+
+    save_manifest(repo, &manifest)
+}
+
+/// Occurrences of each term in the string values of a document
+fn counts(content: &Value) -> HashMap<String, u32> {
+    let mut counts: HashMap<String, u32> = HashMap::new();
+    for (_, text) in strings(content, String::new()) {
+        for term in terms(text.as_str()) {
+            *counts.entry(term).or_default() += 1;
+        }
+    }
+
+    counts
+}
+
+fn load_manifest(repo: &StupidRepository) -> Result<Manifest, StupidDbError> {
+    let dir = search_dir(repo);
+    if dir.is_err() {
+        return Err(dir.err().unwrap());
+    }
+
+    load(dir.unwrap().join(MANIFEST_FILE))
+}
+
+fn save_manifest(repo: &StupidRepository, manifest: &Manifest) -> Result<(), StupidDbError> {
+    let dir = search_dir(repo);
+    if dir.is_err() {
+        return Err(dir.err().unwrap());
+    }
+
+    save(dir.unwrap().join(MANIFEST_FILE), manifest)
+}
+
+fn load_shard(repo: &StupidRepository, file: &str) -> Result<Shard, StupidDbError> {
+    let dir = search_dir(repo);
+    if dir.is_err() {
+        return Err(dir.err().unwrap());
+    }
+
+    load(dir.unwrap().join(file))
+}
+
+fn save_shard(repo: &StupidRepository, file: &str, shard: &Shard) -> Result<(), StupidDbError> {
+    let dir = search_dir(repo);
+    if dir.is_err() {
+        return Err(dir.err().unwrap());
+    }
+
+    let dir = dir.unwrap();
+    // An emptied shard is as good as none
+    if shard.terms.is_empty() {
+        let path = dir.join(file);
+        if path.exists() && fs::remove_file(path).is_err() {
+            return Err(StupidDbError::IndexStoreFailed);
+        }
+        return Ok(());
+    }
+
+    save(dir.join(file), shard)
+}
+
+fn load<T: Default + for<'de> Deserialize<'de>>(file: PathBuf) -> Result<T, StupidDbError> {
+    if !file.exists() {
+        return Ok(T::default());
+    }
+
+    fs::read_to_string(file)
+        .ok()
+        .and_then(|c| serde_json::from_str(c.as_str()).ok())
+        .ok_or(StupidDbError::IndexStoreFailed)
+}
+
+fn save<T: Serialize>(file: PathBuf, value: &T) -> Result<(), StupidDbError> {
+    // Written aside and renamed, a search never reads half a shard
+    let temporary = file.with_extension("tmp");
+    let content = serde_json::to_string(value).unwrap();
+    fs::write(&temporary, content)
+        .and_then(|_| fs::rename(&temporary, file))
+        .map_err(|_| StupidDbError::IndexStoreFailed)
+}
+
+fn search_dir(repo: &StupidRepository) -> Result<PathBuf, StupidDbError> {
+    let dir = repo.data_dir();
+    if dir.is_err() {
+        return Err(dir.err().unwrap());
+    }
+
+    let dir = dir.unwrap().join(SEARCH_DIR);
+    match fs::create_dir_all(&dir) {
+        Ok(_) => Ok(dir),
+        Err(_) => Err(StupidDbError::IndexStoreFailed),
+    }
+}
+
+/// Terms are spread over 256 shards by the first byte of their digest
+fn shard_file(term: &str) -> String {
+    let digest = digest::digest(&digest::SHA256, term.as_bytes());
+    format!("{:02x}.json", digest.as_ref()[0])
+}
+
+/// Lower case runs of letters and digits
+fn terms(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_lowercase())
+        .collect()
+}
+
+/// String leaves of a value with their JSON Pointer, sealed values left
+/// out
+fn strings(value: &Value, path: String) -> Vec<(String, String)> {
+    match value {
+        Value::String(s) => vec![(path, s.to_string())],
+        _ if encryption::is_envelope(value) => Vec::new(),
+        Value::Array(items) => items
+            .iter()
+            .enumerate()
+            .flat_map(|(i, item)| strings(item, format!("{}/{}", path, i)))
+            .collect(),
+        Value::Object(map) => map
+            .iter()
+            .flat_map(|(key, item)| {
+                let token = key.replace('~', "~0").replace('/', "~1");
+                strings(item, format!("{}/{}", path, token))
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// String values holding query terms, the terms marked
+fn highlights(content: &Value, query_terms: &Vec<String>) -> Vec<StupidHighlight> {
+    strings(content, String::new())
+        .into_iter()
+        .filter_map(|(path, text)| {
+            // Terms are matched against the words of the text, not within them
+            let mut words = Vec::new();
+            let mut start = None;
+            for (i, c) in text.char_indices().chain(vec![(text.len(), ' ')]) {
+                match (c.is_alphanumeric(), start) {
+                    (true, None) => start = Some(i),
+                    (false, Some(s)) => {
+                        if query_terms.contains(&text[s..i].to_lowercase()) {
+                            words.push((s, i));
+                        }
+                        start = None;
+                    }
+                    _ => {}
+                }
+            }
+
+            if words.is_empty() {
+                return None;
+            }
+
+            Some(StupidHighlight {
+                path,
+                snippet: snippet(text.as_str(), &words),
+            })
+        })
+        .collect()
+}
+
+/// The text around the first match with all matches within it marked
+fn snippet(text: &str, words: &Vec<(usize, usize)>) -> String {
+    let from = floor_char_boundary(text, words[0].0.saturating_sub(SNIPPET_CONTEXT));
+    let to = floor_char_boundary(text, (words[0].1 + SNIPPET_CONTEXT).min(text.len()));
+    let mut snippet = String::new();
+    if from > 0 {
+        snippet.push_str("...");
+    }
+
+    // Only the highlight tags are markup, the text of documents is escaped
+    let mut position = from;
+    for (start, end) in words.iter().filter(|(s, e)| *s >= from && *e <= to) {
+        snippet.push_str(escape(&text[position..*start]).as_str());
+        snippet.push_str(HIGHLIGHT_START);
+        snippet.push_str(escape(&text[*start..*end]).as_str());
+        snippet.push_str(HIGHLIGHT_END);
+        position = *end;
+    }
+    snippet.push_str(escape(&text[position..to]).as_str());
+
+    if to < text.len() {
+        snippet.push_str("...");
+    }
+
+    snippet
+}
+
+/// Escapes text for use within HTML
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn floor_char_boundary(text: &str, mut index: usize) -> usize {
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+
+    index
+}
@@ -0,0 +1,208 @@
+//! Signing commits with a key held by the server and checking the
+//! signatures of commits.
+//!
+//! Signing and verifying is left to ssh-keygen or gpg, the way git itself
+//! does it, so keys never have to be loaded by stupiddb.
+
+use super::error::StupidDbError;
+use super::models::{StupidSignatureStatus, StupidSigningFormat, StupidSigningSettings};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+use uuid::Uuid;
+
+/// Namespace of ssh signatures made for git objects
+const SSH_NAMESPACE: &str = "git";
+const SSH_ARMOR: &str = "-----BEGIN SSH SIGNATURE-----";
+const GPG_GOOD: &str = "[GNUPG:] GOODSIG ";
+const GPG_BAD: &str = "[GNUPG:] BADSIG ";
+/// Checked commits remembered before the cache starts over
+const CHECKED_CAPACITY: usize = 10000;
+
+lazy_static! {
+    /// Statuses by commit id and trusted keys, a commit never changes so
+    /// neither does its status until the keys do
+    static ref CHECKED: Mutex<HashMap<(String, String), StupidSignatureStatus>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Signs the buffer of a commit with a key, the armored signature
+pub fn sign(
+    settings: &StupidSigningSettings,
+    key: &str,
+    buffer: &str,
+) -> Result<String, StupidDbError> {
+    let mut command = Command::new(program(settings, settings.format));
+    match settings.format {
+        StupidSigningFormat::Ssh => command.args(&["-Y", "sign", "-n", SSH_NAMESPACE, "-f", key]),
+        StupidSigningFormat::Gpg => {
+            command.args(&["--batch", "--detach-sign", "--armor", "--local-user", key])
+        }
+    };
+
+    run(command, buffer.as_bytes())
+        .filter(|o| o.status.success() && !o.stdout.is_empty())
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .ok_or(StupidDbError::SigningFailed)
+}
+
+/// Checks the signature of a signed commit, once per commit as long as
+/// the trusted keys stay the same
+pub fn verify_commit(
+    settings: &StupidSigningSettings,
+    id: &str,
+    signature: &[u8],
+    data: &[u8],
+) -> StupidSignatureStatus {
+    let key = (String::from(id), trust(settings));
+    if let Some(status) = CHECKED.lock().unwrap().get(&key) {
+        return *status;
+    }
+
+    // A check that could not be made may succeed later
+    let status = verify(settings, signature, data);
+    if status != StupidSignatureStatus::Unknown {
+        let mut checked = CHECKED.lock().unwrap();
+        if checked.len() >= CHECKED_CAPACITY {
+            checked.clear();
+        }
+        checked.insert(key, status);
+    }
+
+    status
+}
+
+/// Checks the signature of signed commit data, the format being told by
+/// the signature itself
+fn verify(
+    settings: &StupidSigningSettings,
+    signature: &[u8],
+    data: &[u8],
+) -> StupidSignatureStatus {
+    let format = match signature.starts_with(SSH_ARMOR.as_bytes()) {
+        true => StupidSigningFormat::Ssh,
+        false => StupidSigningFormat::Gpg,
+    };
+
+    // Both programs want the signature in a file and the data on stdin
+    let path = env::temp_dir().join(format!("stupiddb-{}.sig", Uuid::new_v4()));
+    if fs::write(&path, signature).is_err() {
+        return StupidSignatureStatus::Unknown;
+    }
+
+    let status = match format {
+        StupidSigningFormat::Ssh => verify_ssh(settings, &path, data),
+        StupidSigningFormat::Gpg => verify_gpg(settings, &path, data),
+    };
+    let _ = fs::remove_file(&path);
+    status
+}
+
+fn verify_ssh(
+    settings: &StupidSigningSettings,
+    signature: &Path,
+    data: &[u8],
+) -> StupidSignatureStatus {
+    if settings.allowed_signers.is_none() {
+        return StupidSignatureStatus::Unknown;
+    }
+
+    // Signers are checked against the principal their key is allowed for
+    let allowed_signers = settings.allowed_signers.as_ref().unwrap().as_str();
+    let mut find = Command::new(program(settings, StupidSigningFormat::Ssh));
+    find.args(&["-Y", "find-principals", "-f", allowed_signers, "-s"])
+        .arg(signature);
+    let principal = run(find, &[])
+        .filter(|o| o.status.success())
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .and_then(|p| p.lines().next().map(String::from));
+    if principal.is_none() {
+        return StupidSignatureStatus::Unknown;
+    }
+
+    let mut verify = Command::new(program(settings, StupidSigningFormat::Ssh));
+    verify
+        .args(&["-Y", "verify", "-n", SSH_NAMESPACE, "-f", allowed_signers])
+        .args(&["-I", principal.unwrap().as_str(), "-s"])
+        .arg(signature);
+    match run(verify, data) {
+        Some(o) if o.status.success() => StupidSignatureStatus::Good,
+        Some(_) => StupidSignatureStatus::Bad,
+        None => StupidSignatureStatus::Unknown,
+    }
+}
+
+fn verify_gpg(
+    settings: &StupidSigningSettings,
+    signature: &Path,
+    data: &[u8],
+) -> StupidSignatureStatus {
+    let mut verify = Command::new(program(settings, StupidSigningFormat::Gpg));
+    verify
+        .args(&["--batch", "--status-fd", "1", "--verify"])
+        .arg(signature)
+        .arg("-");
+    let status = run(verify, data).map(|o| String::from_utf8_lossy(&o.stdout).into_owned());
+    match status {
+        Some(s) if s.lines().any(|l| l.starts_with(GPG_BAD)) => StupidSignatureStatus::Bad,
+        Some(s) if s.lines().any(|l| l.starts_with(GPG_GOOD)) => StupidSignatureStatus::Good,
+        _ => StupidSignatureStatus::Unknown,
+    }
+}
+
+/// Identifies the trusted keys by the allowed signers file as last
+/// modified. Keys added to or removed from a gpg keyring are not noticed.
+fn trust(settings: &StupidSigningSettings) -> String {
+    let allowed_signers = settings.allowed_signers.as_ref();
+    let modified = allowed_signers
+        .and_then(|p| fs::metadata(p).and_then(|m| m.modified()).ok())
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_nanos());
+    format!(
+        "{}:{}",
+        allowed_signers.map_or("", |p| p.as_str()),
+        modified
+    )
+}
+
+/// Program of a format, the configured one when it is the format signed
+/// with
+fn program(settings: &StupidSigningSettings, format: StupidSigningFormat) -> String {
+    let configured = settings
+        .program
+        .as_ref()
+        .filter(|_| settings.format == format);
+    match (configured, format) {
+        (Some(p), _) => p.to_string(),
+        (None, StupidSigningFormat::Ssh) => String::from("ssh-keygen"),
+        (None, StupidSigningFormat::Gpg) => String::from("gpg"),
+    }
+}
+
+/// Runs a program with the input on stdin, None when it could not be run
+fn run(mut command: Command, input: &[u8]) -> Option<Output> {
+    let child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn();
+    if child.is_err() {
+        return None;
+    }
+
+    let mut child = child.unwrap();
+    let written = child.stdin.take().map(|mut stdin| stdin.write_all(input));
+    if let Some(Err(_)) = written {
+        let _ = child.kill();
+        let _ = child.wait();
+        return None;
+    }
+
+    child.wait_with_output().ok()
+}
@@ -0,0 +1,344 @@
+use super::cache::SharedCache;
+use super::config::SharedConfig;
+use super::error::StupidDbError;
+use super::events;
+use super::models::{StupidDelivery, StupidWebhook, StupidWebhookEvent, StupidWebhookPayload};
+use super::repository::StupidRepository;
+use super::StupidDb;
+use hyper::header::{ContentType, Headers};
+use hyper::net::HttpsConnector;
+use hyper_native_tls::NativeTlsClient;
+use lazy_static::lazy_static;
+use ring::{digest, hmac};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+const WEBHOOKS_FILE: &str = "webhooks.json";
+const DELIVERIES_FILE: &str = "deliveries.json";
+/// Deliveries kept in the log of a repository
+const MAX_DELIVERIES: usize = 200;
+const MAX_ATTEMPTS: u32 = 5;
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    /// Requests adding and removing webhooks rewrite the same file
+    static ref WEBHOOKS_LOCK: Mutex<()> = Mutex::new(());
+    /// Deliveries waiting for the delivery worker, started along with it
+    static ref QUEUE: Mutex<Sender<PendingDelivery>> = {
+        let (sender, receiver) = channel();
+        thread::spawn(move || work(receiver));
+        Mutex::new(sender)
+    };
+}
+
+/// A payload on its way to a webhook
+struct PendingDelivery {
+    dir: PathBuf,
+    webhook: StupidWebhook,
+    payload: StupidWebhookPayload,
+    attempt: u32,
+    due: Instant,
+}
+
+/// Starts delivering commit events to the webhooks of their repository
+pub fn start(config: &SharedConfig, cache: &SharedCache) {
+    let config = config.clone();
+    let cache = cache.clone();
+    let receiver = events::subscribe();
+    thread::spawn(move || {
+        for event in receiver.iter() {
+            let mut db = StupidDb::new(&config, &cache);
+            let repository = db.open(&event.repository);
+            if repository.is_err() {
+                continue;
+            }
+
+            let data = serde_json::to_value(&event).unwrap_or_default();
+            notify(repository.unwrap(), StupidWebhookEvent::Commit, data);
+        }
+    });
+}
+
+/// Sends an event to every webhook of the repository registered for it.
+///
+/// Deliveries are made one at a time by a worker in the background,
+/// failures are retried with an exponential backoff and every attempt ends
+/// up in the delivery log.
+pub fn notify(repo: &StupidRepository, event: StupidWebhookEvent, data: serde_json::Value) {
+    let webhooks = list(repo);
+    let dir = repo.data_dir();
+    if webhooks.is_err() || dir.is_err() {
+        return;
+    }
+
+    let dir = dir.unwrap();
+    for webhook in webhooks.unwrap() {
+        if !webhook.events.contains(&event) {
+            continue;
+        }
+
+        let payload = StupidWebhookPayload {
+            delivery: Uuid::new_v4().to_string(),
+            event,
+            repository: repo.name(),
+            data: data.clone(),
+        };
+        let _ = QUEUE.lock().unwrap().send(PendingDelivery {
+            dir: dir.clone(),
+            webhook,
+            payload,
+            attempt: 1,
+            due: Instant::now(),
+        });
+    }
+}
+
+pub fn list(repo: &StupidRepository) -> Result<Vec<StupidWebhook>, StupidDbError> {
+    read(repo, WEBHOOKS_FILE)
+}
+
+pub fn add(
+    repo: &StupidRepository,
+    webhook: &StupidWebhook,
+) -> Result<StupidWebhook, StupidDbError> {
+    let _guard = WEBHOOKS_LOCK.lock().unwrap();
+    let webhooks = list(repo);
+    if webhooks.is_err() {
+        return Err(webhooks.err().unwrap());
+    }
+
+    let mut webhook = webhook.clone();
+    webhook.id = Uuid::new_v4().to_string();
+
+    let mut webhooks = webhooks.unwrap();
+    webhooks.push(webhook.clone());
+    write(repo, WEBHOOKS_FILE, &webhooks).map(|_| webhook)
+}
+
+pub fn remove(repo: &StupidRepository, id: &str) -> Result<(), StupidDbError> {
+    let _guard = WEBHOOKS_LOCK.lock().unwrap();
+    let webhooks = list(repo);
+    if webhooks.is_err() {
+        return Err(webhooks.err().unwrap());
+    }
+
+    let mut webhooks = webhooks.unwrap();
+    let length = webhooks.len();
+    webhooks.retain(|w| w.id != id);
+    if webhooks.len() == length {
+        return Err(StupidDbError::WebhookNotFound);
+    }
+
+    write(repo, WEBHOOKS_FILE, &webhooks)
+}
+
+/// Delivery attempts of a webhook, oldest first
+pub fn deliveries(repo: &StupidRepository, id: &str) -> Result<Vec<StupidDelivery>, StupidDbError> {
+    let webhooks = list(repo);
+    if webhooks.is_err() {
+        return Err(webhooks.err().unwrap());
+    }
+
+    if !webhooks.unwrap().iter().any(|w| w.id == id) {
+        return Err(StupidDbError::WebhookNotFound);
+    }
+
+    read(repo, DELIVERIES_FILE).map(|deliveries: Vec<StupidDelivery>| {
+        deliveries.into_iter().filter(|d| d.webhook == id).collect()
+    })
+}
+
+/// Sends the queued deliveries one after the other, keeping the failed ones
+/// aside until their backoff has passed
+fn work(queue: Receiver<PendingDelivery>) {
+    let mut pending: Vec<PendingDelivery> = Vec::new();
+    loop {
+        let received = match pending.iter().map(|p| p.due).min() {
+            None => queue.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            Some(due) => queue.recv_timeout(due.saturating_duration_since(Instant::now())),
+        };
+        match received {
+            Ok(delivery) => pending.push(delivery),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+
+        let now = Instant::now();
+        let (due, waiting): (Vec<PendingDelivery>, Vec<PendingDelivery>) =
+            pending.into_iter().partition(|p| p.due <= now);
+        pending = waiting;
+        for delivery in due {
+            if let Some(retry) = deliver(delivery) {
+                pending.push(retry);
+            }
+        }
+    }
+}
+
+/// Makes an attempt at a delivery, the next attempt when it failed and
+/// attempts are left
+fn deliver(delivery: PendingDelivery) -> Option<PendingDelivery> {
+    let PendingDelivery {
+        dir,
+        webhook,
+        payload,
+        attempt,
+        ..
+    } = delivery;
+    let body = serde_json::to_string(&payload).unwrap();
+    let status = send(&webhook, &payload, body.as_str());
+    let delivered = status.as_ref().map_or(false, |s| s.is_success());
+    log(
+        dir.as_path(),
+        StupidDelivery {
+            id: payload.delivery.to_string(),
+            webhook: webhook.id.to_string(),
+            event: payload.event,
+            attempt,
+            time: now(),
+            status: status.as_ref().ok().map(|s| s.to_u16()),
+            error: match status {
+                Ok(ref s) if !delivered => Some(format!("{}", s)),
+                Ok(_) => None,
+                Err(e) => Some(e),
+            },
+            delivered,
+        },
+    );
+
+    if delivered || attempt == MAX_ATTEMPTS {
+        return None;
+    }
+
+    Some(PendingDelivery {
+        dir,
+        webhook,
+        payload,
+        attempt: attempt + 1,
+        due: Instant::now() + FIRST_BACKOFF * 2u32.pow(attempt - 1),
+    })
+}
+
+fn send(
+    webhook: &StupidWebhook,
+    payload: &StupidWebhookPayload,
+    body: &str,
+) -> Result<hyper::status::StatusCode, String> {
+    let event = serde_json::to_value(&payload.event).unwrap();
+    let mut headers = Headers::new();
+    headers.set(ContentType::json());
+    headers.set_raw(
+        "X-StupidDb-Event",
+        vec![event.as_str().unwrap_or_default().as_bytes().to_vec()],
+    );
+    headers.set_raw(
+        "X-StupidDb-Delivery",
+        vec![payload.delivery.as_bytes().to_vec()],
+    );
+    if let Some(secret) = webhook.secret.as_ref() {
+        headers.set_raw(
+            "X-StupidDb-Signature",
+            vec![format!("sha256={}", sign(secret, body)).into_bytes()],
+        );
+    }
+
+    // The connector speaks TLS to https URLs only
+    let tls = NativeTlsClient::new();
+    if tls.is_err() {
+        return Err(tls.err().unwrap().to_string());
+    }
+
+    let mut client = hyper::Client::with_connector(HttpsConnector::new(tls.unwrap()));
+    client.set_read_timeout(Some(TIMEOUT));
+    client.set_write_timeout(Some(TIMEOUT));
+    client
+        .post(webhook.url.as_str())
+        .headers(headers)
+        .body(body)
+        .send()
+        .map(|response| response.status)
+        .map_err(|e| e.to_string())
+}
+
+/// Hex encoded HMAC-SHA256 of the body
+fn sign(secret: &str, body: &str) -> String {
+    let key = hmac::SigningKey::new(&digest::SHA256, secret.as_bytes());
+    hmac::sign(&key, body.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Only the delivery worker writes the log
+fn log(dir: &Path, delivery: StupidDelivery) {
+    let path = dir.join(DELIVERIES_FILE);
+    let mut deliveries: Vec<StupidDelivery> = fs::read_to_string(&path)
+        .ok()
+        .and_then(|c| serde_json::from_str(c.as_str()).ok())
+        .unwrap_or_default();
+
+    deliveries.push(delivery);
+    if deliveries.len() > MAX_DELIVERIES {
+        deliveries.drain(..deliveries.len() - MAX_DELIVERIES);
+    }
+
+    let _ = store(path, serde_json::to_string(&deliveries).unwrap());
+}
+
+fn read<T: serde::de::DeserializeOwned>(
+    repo: &StupidRepository,
+    file: &str,
+) -> Result<Vec<T>, StupidDbError> {
+    let path = file_path(repo, file);
+    if path.is_err() {
+        return Err(path.err().unwrap());
+    }
+
+    let path = path.unwrap();
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|c| serde_json::from_str(c.as_str()).ok())
+        .ok_or(StupidDbError::WebhookStoreFailed)
+}
+
+fn write<T: serde::Serialize>(
+    repo: &StupidRepository,
+    file: &str,
+    content: &Vec<T>,
+) -> Result<(), StupidDbError> {
+    let path = file_path(repo, file);
+    if path.is_err() {
+        return Err(path.err().unwrap());
+    }
+
+    let content = serde_json::to_string(content).unwrap();
+    store(path.unwrap(), content).map_err(|_| StupidDbError::WebhookStoreFailed)
+}
+
+/// Writes a file aside and renames it, readers never see half of it
+fn store(path: PathBuf, content: String) -> std::io::Result<()> {
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, content).and_then(|_| fs::rename(&temporary, path))
+}
+
+fn file_path(repo: &StupidRepository, file: &str) -> Result<PathBuf, StupidDbError> {
+    repo.data_dir().map(|dir| dir.join(file))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}